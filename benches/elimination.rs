@@ -0,0 +1,45 @@
+//! Throughput of `EliminationStack` against a plain `LockFreeStack`.
+//!
+//! Run with `cargo bench --bench elimination`. Every thread alternates between
+//! push and pop, which is the pattern elimination helps with most.
+
+use lock_free::{EliminationStack, LockFreeStack};
+use std::{thread, time::Instant};
+
+const OPS_PER_THREAD: usize = 100_000;
+
+/// Runs `op` on `threads` threads and returns the throughput in million ops/s.
+fn measure(threads: usize, op: impl Fn(usize) + Sync) -> f64 {
+    let start = Instant::now();
+    thread::scope(|s| {
+        for t in 0..threads {
+            let op = &op;
+            s.spawn(move || {
+                for i in 0..OPS_PER_THREAD {
+                    op(t * OPS_PER_THREAD + i);
+                }
+            });
+        }
+    });
+    (threads * OPS_PER_THREAD * 2) as f64 / start.elapsed().as_secs_f64() / 1e6
+}
+
+fn main() {
+    println!(
+        "{:>8} {:>14} {:>14}",
+        "threads", "treiber Mop/s", "elim. Mop/s"
+    );
+    for threads in [1, 2, 4, 8, 16, 32, 64] {
+        let stack = LockFreeStack::new();
+        let treiber = measure(threads, |i| {
+            stack.push(i);
+            stack.pop();
+        });
+        let stack = EliminationStack::new();
+        let elimination = measure(threads, |i| {
+            stack.push(i);
+            stack.pop();
+        });
+        println!("{threads:>8} {treiber:>14.2} {elimination:>14.2}");
+    }
+}
@@ -0,0 +1,50 @@
+//! Throughput of `LockFreeStack` with and without its node pool.
+//!
+//! Run with `cargo bench --bench pool`. Every thread pushes a batch and pops it
+//! again, so with the pool on the pushes mostly reuse nodes the pops gave back.
+
+use lock_free::{Epoch, LockFreeStack};
+use std::{thread, time::Instant};
+
+const OPS_PER_THREAD: usize = 100_000;
+const BATCH: usize = 64;
+
+/// Pushes and pops batches on `threads` threads and returns the throughput in
+/// million ops/s.
+fn measure<R: lock_free::reclaim::Reclaim>(threads: usize, stack: &LockFreeStack<usize, R>) -> f64 {
+    let start = Instant::now();
+    thread::scope(|s| {
+        for _ in 0..threads {
+            s.spawn(move || {
+                for i in 0..OPS_PER_THREAD / BATCH {
+                    for j in 0..BATCH {
+                        stack.push(i * BATCH + j);
+                    }
+                    for _ in 0..BATCH {
+                        stack.pop();
+                    }
+                }
+            });
+        }
+    });
+    (threads * OPS_PER_THREAD * 2) as f64 / start.elapsed().as_secs_f64() / 1e6
+}
+
+fn main() {
+    println!(
+        "{:>8} {:>14} {:>14} {:>14} {:>14}",
+        "threads", "hazard Mop/s", "pooled Mop/s", "epoch Mop/s", "pooled Mop/s"
+    );
+    for threads in [1, 2, 4, 8, 16] {
+        let plain = measure(threads, &LockFreeStack::new());
+        let mut stack = LockFreeStack::new();
+        stack.set_pool_capacity(threads * BATCH * 2);
+        let pooled = measure(threads, &stack);
+
+        let epoch = measure(threads, &LockFreeStack::with_reclaim(Epoch::new()));
+        let mut stack = LockFreeStack::with_reclaim(Epoch::new());
+        stack.set_pool_capacity(threads * BATCH * 2);
+        let epoch_pooled = measure(threads, &stack);
+        println!("{threads:>8} {plain:>14.2} {pooled:>14.2} {epoch:>14.2} {epoch_pooled:>14.2}");
+    }
+}
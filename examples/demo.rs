@@ -0,0 +1,27 @@
+use lock_free::{reclaim::Reclaim, Epoch, LockFreeStack};
+use std::{thread, time::Instant};
+
+fn main() {
+    run("hazard pointers", LockFreeStack::new());
+    run("epoch", LockFreeStack::with_reclaim(Epoch::new()));
+}
+
+/// Hammers the stack from 10 threads so the reclamation schemes can be compared
+fn run<R: Reclaim>(name: &str, mut stack: LockFreeStack<i32, R>) {
+    let start = Instant::now();
+    thread::scope(|s| {
+        for i in 0..10 {
+            let stack = &stack;
+            s.spawn(move || {
+                for _ in 0..1000 {
+                    stack.push(i);
+                    stack.pop();
+                    stack.push(i);
+                }
+            });
+        }
+    });
+    println!("{name}: {:?}", start.elapsed());
+    println!("len: {}", stack.len());
+    println!("top element: {:?}", stack.pop());
+}
@@ -0,0 +1,79 @@
+//! Allocators the stack can take the memory for its nodes from.
+//!
+//! A [`LockFreeStack`](crate::LockFreeStack) allocates a node for every push and
+//! frees it once no other thread can still read it, through a [`NodeAllocator`].
+//! The default is [`Global`], the allocator `Box` uses as well.
+//!
+//! ```
+//! use lock_free::{allocator::{Global, NodeAllocator}, LockFreeStack};
+//! use std::{alloc::Layout, ptr::NonNull, sync::atomic::{AtomicUsize, Ordering}};
+//!
+//! /// Counts the nodes that are currently allocated.
+//! #[derive(Default)]
+//! struct Counting(AtomicUsize);
+//!
+//! unsafe impl NodeAllocator for Counting {
+//!     fn allocate(&self, layout: Layout) -> Option<NonNull<u8>> {
+//!         self.0.fetch_add(1, Ordering::Relaxed);
+//!         Global.allocate(layout)
+//!     }
+//!
+//!     unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+//!         self.0.fetch_sub(1, Ordering::Relaxed);
+//!         unsafe { Global.deallocate(ptr, layout) }
+//!     }
+//! }
+//!
+//! let stack = LockFreeStack::with_allocator(Counting::default());
+//! stack.push(1);
+//! assert_eq!(stack.allocator().0.load(Ordering::Relaxed), 1);
+//! ```
+
+use core::{alloc::Layout, ptr::NonNull};
+
+/// A source of memory for nodes.
+///
+/// Both methods take `&self` because every thread that pushes or pops allocates
+/// and frees nodes at the same time, so mutable state has to live in atomics or
+/// behind a lock. The layouts asked for are never zero-sized.
+///
+/// # Safety
+/// A block returned by [`allocate`](Self::allocate) must fit `layout` and stay
+/// valid, without being handed out again, until it is passed to
+/// [`deallocate`](Self::deallocate).
+pub unsafe trait NodeAllocator: Send + Sync {
+    /// Returns a block for `layout`, or `None` if the allocator is exhausted.
+    fn allocate(&self, layout: Layout) -> Option<NonNull<u8>>;
+
+    /// Takes back a block.
+    ///
+    /// # Safety
+    /// `ptr` must come from [`allocate`](Self::allocate) on this allocator with
+    /// the same `layout` and must not be used afterwards.
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout);
+}
+
+/// The global allocator.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Global;
+
+unsafe impl NodeAllocator for Global {
+    fn allocate(&self, layout: Layout) -> Option<NonNull<u8>> {
+        NonNull::new(unsafe { alloc::alloc::alloc(layout) })
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        unsafe { alloc::alloc::dealloc(ptr.as_ptr(), layout) }
+    }
+}
+
+/// Lets several stacks share one allocator, an arena for example.
+unsafe impl<A: NodeAllocator + ?Sized> NodeAllocator for &A {
+    fn allocate(&self, layout: Layout) -> Option<NonNull<u8>> {
+        (**self).allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        unsafe { (**self).deallocate(ptr, layout) }
+    }
+}
@@ -0,0 +1,293 @@
+use crate::cache_padded::CachePadded;
+use alloc::boxed::Box;
+use core::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    sync::atomic::{fence, AtomicUsize, Ordering},
+};
+
+/// A slot in the buffer. Its stamp says whether it is ready to be written (`stamp ==
+/// tail`) or read (`stamp == head + 1`) by the operation at that position.
+struct Slot<T> {
+    stamp: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+/// A bounded lock-free MPMC queue backed by a fixed array (Vyukov).
+///
+/// It never allocates after construction. `try_push` hands the value back when the
+/// queue is full, so producers can apply backpressure.
+///
+/// ```
+/// use lock_free::ArrayQueue;
+///
+/// let queue = ArrayQueue::new(1);
+/// assert_eq!(queue.try_push(1), Ok(()));
+/// assert_eq!(queue.try_push(2), Err(2));
+/// assert_eq!(queue.try_pop(), Some(1));
+/// ```
+pub struct ArrayQueue<T> {
+    // `head` and `tail` are stamps: the low bits index the buffer and the high bits
+    // count laps around it, so a stamp is not reused until the counter wraps
+    head: CachePadded<AtomicUsize>,
+    tail: CachePadded<AtomicUsize>,
+    buffer: Box<[Slot<T>]>,
+    /// Added to a stamp to move it to the same index on the next lap.
+    one_lap: usize,
+}
+
+// The cells are what keeps the queue from being `Sync`, it is `Send` for `T: Send`
+// on its own. A cell is only touched by the push or pop whose CAS moved `tail` or
+// `head` past the slot's stamp, and the stamp it stores afterwards hands the slot
+// on, from a push to the pop at the same position and from a pop to the push a
+// lap ahead. So every value passes from one push to one pop and `T: Send` is
+// enough.
+unsafe impl<T: Send> Sync for ArrayQueue<T> {}
+
+impl<T> ArrayQueue<T> {
+    /// Creates an empty queue that holds at most `capacity` values.
+    ///
+    /// # Panics
+    /// If `capacity` is zero.
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "capacity must be non-zero");
+        let buffer = (0..capacity)
+            .map(|i| Slot {
+                stamp: AtomicUsize::new(i),
+                value: UnsafeCell::new(MaybeUninit::uninit()),
+            })
+            .collect();
+        Self {
+            head: CachePadded::new(AtomicUsize::new(0)),
+            tail: CachePadded::new(AtomicUsize::new(0)),
+            buffer,
+            one_lap: (capacity + 1).next_power_of_two(),
+        }
+    }
+
+    /// The stamp following `stamp`, wrapping to the start of the next lap at the end
+    /// of the buffer.
+    fn next_stamp(&self, stamp: usize) -> usize {
+        let index = stamp & (self.one_lap - 1);
+        if index + 1 < self.buffer.len() {
+            stamp + 1
+        } else {
+            (stamp & !(self.one_lap - 1)).wrapping_add(self.one_lap)
+        }
+    }
+
+    /// Pushes `value`, or returns it back if the queue is full.
+    pub fn try_push(&self, value: T) -> Result<(), T> {
+        let mut tail = self.tail.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.buffer[tail & (self.one_lap - 1)];
+            let stamp = slot.stamp.load(Ordering::Acquire);
+
+            if stamp == tail {
+                // the slot is free, claim it by moving tail past it
+                match self.tail.compare_exchange_weak(
+                    tail,
+                    self.next_stamp(tail),
+                    Ordering::SeqCst,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        unsafe { (*slot.value.get()).write(value) };
+                        slot.stamp.store(tail + 1, Ordering::Release);
+                        return Ok(());
+                    }
+                    Err(actual) => tail = actual,
+                }
+            } else if stamp.wrapping_add(self.one_lap) == tail + 1 {
+                // the slot still holds the value from the previous lap, we are full
+                // unless a pop has already claimed it
+                fence(Ordering::SeqCst);
+                let head = self.head.load(Ordering::Relaxed);
+                if head.wrapping_add(self.one_lap) == tail {
+                    return Err(value);
+                }
+                tail = self.tail.load(Ordering::Relaxed);
+            } else {
+                // another push claimed this position, catch up
+                tail = self.tail.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Pops the oldest value, or returns `None` if the queue is empty.
+    pub fn try_pop(&self) -> Option<T> {
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.buffer[head & (self.one_lap - 1)];
+            let stamp = slot.stamp.load(Ordering::Acquire);
+
+            if stamp == head + 1 {
+                // the slot holds a value, claim it by moving head past it
+                match self.head.compare_exchange_weak(
+                    head,
+                    self.next_stamp(head),
+                    Ordering::SeqCst,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        let value = unsafe { (*slot.value.get()).assume_init_read() };
+                        // free the slot for the push one lap ahead
+                        slot.stamp
+                            .store(head.wrapping_add(self.one_lap), Ordering::Release);
+                        return Some(value);
+                    }
+                    Err(actual) => head = actual,
+                }
+            } else if stamp == head {
+                // the slot has not been written yet, we are empty unless a push has
+                // already claimed it
+                fence(Ordering::SeqCst);
+                let tail = self.tail.load(Ordering::Relaxed);
+                if tail == head {
+                    return None;
+                }
+                head = self.head.load(Ordering::Relaxed);
+            } else {
+                // another pop claimed this position, catch up
+                head = self.head.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// The number of values in the queue at some point during the call.
+    pub fn len(&self) -> usize {
+        loop {
+            let tail = self.tail.load(Ordering::SeqCst);
+            let head = self.head.load(Ordering::SeqCst);
+            // only trust the pair if tail did not move while we read head
+            if self.tail.load(Ordering::SeqCst) == tail {
+                return self.distance(head, tail);
+            }
+        }
+    }
+
+    /// Number of positions from `head` to `tail`.
+    fn distance(&self, head: usize, tail: usize) -> usize {
+        let head_index = head & (self.one_lap - 1);
+        let tail_index = tail & (self.one_lap - 1);
+        if head_index < tail_index {
+            tail_index - head_index
+        } else if head_index > tail_index {
+            self.capacity() - head_index + tail_index
+        } else if tail == head {
+            0
+        } else {
+            self.capacity()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len() == self.capacity()
+    }
+}
+
+impl<T> Drop for ArrayQueue<T> {
+    fn drop(&mut self) {
+        let mut head = self.head.load(Ordering::Relaxed);
+        for _ in 0..self.len() {
+            let slot = &mut self.buffer[head & (self.one_lap - 1)];
+            unsafe { slot.value.get_mut().assume_init_drop() };
+            head = self.next_stamp(head);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::Arc, thread};
+
+    #[test]
+    fn test_full_and_empty() {
+        let queue = ArrayQueue::new(3);
+        assert_eq!(queue.try_pop(), None);
+        for i in 0..3 {
+            assert_eq!(queue.try_push(i), Ok(()));
+        }
+        assert!(queue.is_full());
+        assert_eq!(queue.try_push(3), Err(3));
+        for i in 0..3 {
+            assert_eq!(queue.try_pop(), Some(i));
+        }
+        assert!(queue.is_empty());
+        assert_eq!(queue.try_pop(), None);
+    }
+
+    #[test]
+    fn test_wraps_around() {
+        let queue = ArrayQueue::new(3);
+        for i in 0..100 {
+            queue.try_push(i).unwrap();
+            queue.try_push(i + 1000).unwrap();
+            assert_eq!(queue.len(), 2);
+            assert_eq!(queue.try_pop(), Some(i));
+            assert_eq!(queue.try_pop(), Some(i + 1000));
+        }
+    }
+
+    #[test]
+    fn test_mpmc() {
+        let queue = ArrayQueue::new(64);
+        let total: u64 = thread::scope(|s| {
+            for producer in 0..4 {
+                let queue = &queue;
+                s.spawn(move || {
+                    for i in 0..10000_u64 {
+                        let mut value = producer * 10000 + i;
+                        // spin until there is room, this is the backpressure
+                        while let Err(rejected) = queue.try_push(value) {
+                            value = rejected;
+                        }
+                    }
+                });
+            }
+            let consumers: Vec<_> = (0..4)
+                .map(|_| {
+                    s.spawn(|| {
+                        let mut sum = 0;
+                        for _ in 0..10000 {
+                            loop {
+                                if let Some(value) = queue.try_pop() {
+                                    sum += value;
+                                    break;
+                                }
+                            }
+                        }
+                        sum
+                    })
+                })
+                .collect();
+            consumers.into_iter().map(|h| h.join().unwrap()).sum()
+        });
+        assert_eq!(total, (0..40000).sum());
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_drop_remaining_values() {
+        let value = Arc::new(());
+        let queue = ArrayQueue::new(8);
+        for _ in 0..12 {
+            queue.try_push(value.clone()).unwrap();
+            if Arc::strong_count(&value) > 6 {
+                queue.try_pop();
+            }
+        }
+        assert_eq!(Arc::strong_count(&value), 6);
+        drop(queue);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+}
@@ -0,0 +1,202 @@
+//! Backoff strategies for CAS retry loops.
+//!
+//! A structure keeps one configured strategy and clones it at the start of every
+//! operation, so each retry loop starts from a fresh state. The strategy's
+//! [`backoff`](Backoff::backoff) is called after every failed attempt.
+
+use core::hint;
+#[cfg(feature = "std")]
+use std::thread;
+
+/// What a retry loop does between a failed CAS and its next attempt.
+pub trait Backoff: Clone + Send + Sync {
+    /// Waits before the next attempt.
+    fn backoff(&mut self);
+}
+
+/// The largest limit the spinning strategies accept, larger ones are clamped to
+/// it. `2^16` spins is already far longer than any CAS is worth waiting for.
+pub const MAX_LIMIT: u32 = 16;
+
+/// Retries right away.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoBackoff;
+
+impl Backoff for NoBackoff {
+    fn backoff(&mut self) {}
+}
+
+/// Spins for twice as long after every failed attempt, up to `2^limit` spins.
+/// `limit` is clamped to [`MAX_LIMIT`].
+#[derive(Clone, Copy, Debug)]
+pub struct ExponentialSpin {
+    step: u32,
+    limit: u32,
+}
+
+impl ExponentialSpin {
+    pub fn new(limit: u32) -> Self {
+        Self {
+            step: 0,
+            limit: limit.min(MAX_LIMIT),
+        }
+    }
+}
+
+impl Default for ExponentialSpin {
+    fn default() -> Self {
+        Self::new(6)
+    }
+}
+
+impl Backoff for ExponentialSpin {
+    fn backoff(&mut self) {
+        for _ in 0..1_u32 << self.step {
+            hint::spin_loop();
+        }
+        self.step = (self.step + 1).min(self.limit);
+    }
+}
+
+/// Spins like [`ExponentialSpin`] until it reaches `2^spin_limit` spins, then
+/// yields the thread to the scheduler on every further attempt. Suits machines
+/// with more threads than cores, where the thread we wait on may not be running.
+/// `spin_limit` is clamped to [`MAX_LIMIT`].
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug)]
+pub struct SpinThenYield {
+    step: u32,
+    spin_limit: u32,
+}
+
+#[cfg(feature = "std")]
+impl SpinThenYield {
+    pub fn new(spin_limit: u32) -> Self {
+        Self {
+            step: 0,
+            spin_limit: spin_limit.min(MAX_LIMIT),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for SpinThenYield {
+    fn default() -> Self {
+        Self::new(6)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Backoff for SpinThenYield {
+    fn backoff(&mut self) {
+        if self.step > self.spin_limit {
+            thread::yield_now();
+            return;
+        }
+        for _ in 0..1_u32 << self.step {
+            hint::spin_loop();
+        }
+        self.step += 1;
+    }
+}
+
+/// Spins for a random number of iterations below a bound that doubles after every
+/// failed attempt, up to `2^limit`. The randomness keeps threads that failed on
+/// the same CAS from retrying in lockstep. `limit` is clamped to [`MAX_LIMIT`].
+#[derive(Clone, Copy, Debug)]
+pub struct Jitter {
+    step: u32,
+    limit: u32,
+    state: u64,
+}
+
+impl Jitter {
+    pub fn new(limit: u32) -> Self {
+        Self {
+            step: 0,
+            limit: limit.min(MAX_LIMIT),
+            state: 0,
+        }
+    }
+
+    /// xorshift64, seeded from the address of the state so that threads which
+    /// clone the same strategy still draw different numbers.
+    fn next_random(&mut self) -> u64 {
+        if self.state == 0 {
+            self.state = (self as *const Self).addr() as u64 | 1;
+        }
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+}
+
+impl Default for Jitter {
+    fn default() -> Self {
+        Self::new(6)
+    }
+}
+
+impl Backoff for Jitter {
+    fn backoff(&mut self) {
+        let bound = 1_u64 << self.step;
+        for _ in 0..self.next_random() % bound + 1 {
+            hint::spin_loop();
+        }
+        self.step = (self.step + 1).min(self.limit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exponential_spin_caps_at_limit() {
+        let mut backoff = ExponentialSpin::new(3);
+        for _ in 0..10 {
+            backoff.backoff();
+        }
+        assert_eq!(backoff.step, 3);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_spin_then_yield_stops_spinning() {
+        let mut backoff = SpinThenYield::new(2);
+        for _ in 0..10 {
+            backoff.backoff();
+        }
+        assert_eq!(backoff.step, 3);
+    }
+
+    #[test]
+    fn test_large_limits_are_clamped() {
+        let mut exponential = ExponentialSpin::new(u32::MAX);
+        let mut jitter = Jitter::new(64);
+        for _ in 0..MAX_LIMIT + 2 {
+            exponential.backoff();
+            jitter.backoff();
+        }
+        assert_eq!(exponential.step, MAX_LIMIT);
+        assert_eq!(jitter.step, MAX_LIMIT);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_spin_then_yield_clamps_its_limit() {
+        let mut backoff = SpinThenYield::new(u32::MAX);
+        for _ in 0..MAX_LIMIT + 4 {
+            backoff.backoff();
+        }
+        assert_eq!(backoff.step, MAX_LIMIT + 1);
+    }
+
+    #[test]
+    fn test_jitter_varies() {
+        let mut backoff = Jitter::new(10);
+        let draws: Vec<_> = (0..8).map(|_| backoff.next_random()).collect();
+        assert!(draws.windows(2).all(|pair| pair[0] != pair[1]));
+    }
+}
@@ -0,0 +1,28 @@
+use core::ops::Deref;
+
+/// Pads and aligns a value to the size of a cache line, so atomics written by
+/// different threads do not keep invalidating each other's line.
+///
+/// x86_64 and aarch64 prefetch cache lines in pairs, so they get 128 bytes.
+#[cfg_attr(any(target_arch = "x86_64", target_arch = "aarch64"), repr(align(128)))]
+#[cfg_attr(
+    not(any(target_arch = "x86_64", target_arch = "aarch64")),
+    repr(align(64))
+)]
+pub(crate) struct CachePadded<T> {
+    value: T,
+}
+
+impl<T> CachePadded<T> {
+    pub(crate) fn new(value: T) -> Self {
+        Self { value }
+    }
+}
+
+impl<T> Deref for CachePadded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
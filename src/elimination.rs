@@ -0,0 +1,223 @@
+use crate::{
+    cache_padded::CachePadded,
+    hazard::HazardPointers,
+    reclaim::Reclaim,
+    stack::{LockFreeStack, Node},
+    tagged::{AtomicTaggedPtr, TaggedPtr},
+};
+use alloc::boxed::Box;
+use core::{hint, ptr, sync::atomic::Ordering};
+
+const DEFAULT_WIDTH: usize = 8;
+const DEFAULT_SPINS: usize = 128;
+
+/// A [`LockFreeStack`] with an elimination array in front of it.
+///
+/// When a push or pop loses the race for `head`, it goes to a random slot of the
+/// elimination array instead of retrying right away. A push leaves its node in the
+/// slot for a while, and a pop that finds it there takes the value directly. The
+/// pair cancels out without either of them touching `head`, so the more contended
+/// the stack is, the more operations bypass it.
+///
+/// ```
+/// use lock_free::EliminationStack;
+///
+/// let stack = EliminationStack::with_config(4, 64);
+/// stack.push(1);
+/// assert_eq!(stack.pop(), Some(1));
+/// ```
+pub struct EliminationStack<T, R: Reclaim = HazardPointers> {
+    stack: LockFreeStack<T, R>,
+    // a slot holds a pushed node waiting for a pop, versioned so a push can tell
+    // whether its own node is still there
+    slots: Box<[CachePadded<AtomicTaggedPtr<Node<T>>>]>,
+    spins: usize,
+}
+
+impl<T> EliminationStack<T> {
+    /// Creates an empty stack with a default elimination array.
+    pub fn new() -> Self {
+        Self::with_config(DEFAULT_WIDTH, DEFAULT_SPINS)
+    }
+
+    /// Creates an empty stack with `width` elimination slots, where a push waits
+    /// for `spins` spin-loop iterations before taking its value back.
+    pub fn with_config(width: usize, spins: usize) -> Self {
+        Self::with_reclaim(HazardPointers::new(), width, spins)
+    }
+}
+
+impl<T> Default for EliminationStack<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, R: Reclaim> EliminationStack<T, R> {
+    /// Like [`with_config`](EliminationStack::with_config), reclaiming popped nodes
+    /// through `reclaim`.
+    ///
+    /// # Panics
+    /// If `width` is zero.
+    pub fn with_reclaim(reclaim: R, width: usize, spins: usize) -> Self {
+        assert!(width > 0, "the elimination array needs at least one slot");
+        Self {
+            stack: LockFreeStack::with_reclaim(reclaim),
+            slots: (0..width)
+                .map(|_| CachePadded::new(AtomicTaggedPtr::new(ptr::null_mut())))
+                .collect(),
+            spins,
+        }
+    }
+
+    pub fn push(&self, data: T) {
+        let node = self.stack.alloc_node(data);
+        let mut attempt = 0;
+        while !self.stack.try_link(node) && !self.offer(node, attempt) {
+            attempt += 1;
+        }
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        let mut guard = self.stack.guard();
+        let mut attempt = 0;
+        loop {
+            if let Ok(data) = self.stack.try_pop(&mut guard) {
+                return data;
+            }
+            if let Some(data) = self.take(&mut guard, attempt) {
+                return Some(data);
+            }
+            attempt += 1;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stack.is_empty()
+    }
+
+    /// Picks a slot from an address that differs between threads, moving on to
+    /// another slot on every attempt.
+    fn slot(&self, address: usize, attempt: usize) -> &AtomicTaggedPtr<Node<T>> {
+        // Fibonacci hashing, so nearby addresses still land on different slots
+        let hash = (address ^ attempt).wrapping_mul(0x9E37_79B9_7F4A_7C15_u64 as usize);
+        &self.slots[(hash >> (usize::BITS / 2)) % self.slots.len()]
+    }
+
+    /// Leaves `node` in a slot for a pop to take. Returns `true` if one did.
+    fn offer(&self, node: *mut Node<T>, attempt: usize) -> bool {
+        let slot = self.slot(node.addr(), attempt);
+        let empty = slot.load(Ordering::Acquire);
+        if !empty.is_null()
+            || slot
+                .compare_exchange(empty, node, Ordering::Release, Ordering::Relaxed)
+                .is_err()
+        {
+            return false;
+        }
+        let offered = TaggedPtr::new(node, empty.tag().wrapping_add(1));
+
+        for _ in 0..self.spins {
+            if slot.load(Ordering::Relaxed) != offered {
+                // only a pop replaces our offer, the node is theirs now
+                return true;
+            }
+            hint::spin_loop();
+        }
+        // take the node back, if that fails a pop got to it first
+        slot.compare_exchange(
+            offered,
+            ptr::null_mut(),
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        )
+        .is_err()
+    }
+
+    /// Takes a node offered by a concurrent push, if the chosen slot has one.
+    fn take(&self, guard: &mut R::Guard<'_>, attempt: usize) -> Option<T> {
+        let local = 0_u8;
+        let slot = self.slot(ptr::addr_of!(local).addr(), attempt);
+        let offered = slot.load(Ordering::Relaxed);
+        if offered.is_null() {
+            return None;
+        }
+        slot.compare_exchange(
+            offered,
+            ptr::null_mut(),
+            Ordering::Acquire,
+            Ordering::Relaxed,
+        )
+        .ok()?;
+        // the node never made it onto the stack, so nobody else can be reading it
+        Some(unsafe { self.stack.take_data(guard, offered.ptr()) })
+    }
+}
+
+#[cfg(all(test, not(feature = "loom")))]
+mod tests {
+    use super::*;
+    use crate::epoch::Epoch;
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+    };
+
+    #[test]
+    fn test_push_pop() {
+        let stack = EliminationStack::new();
+        for i in 0..100 {
+            stack.push(i);
+        }
+        for i in (0..100).rev() {
+            assert_eq!(stack.pop(), Some(i));
+        }
+        assert_eq!(stack.pop(), None);
+    }
+
+    #[test]
+    fn test_contended_push_pop() {
+        let stack = EliminationStack::with_reclaim(Epoch::new(), 2, 1000);
+        let popped = AtomicUsize::new(0);
+        thread::scope(|s| {
+            for i in 0..8 {
+                let stack = &stack;
+                let popped = &popped;
+                s.spawn(move || {
+                    for j in 0..20000 {
+                        stack.push(i * 20000 + j);
+                        if stack.pop().is_some() {
+                            popped.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                });
+            }
+        });
+        let mut remaining = 0;
+        while stack.pop().is_some() {
+            remaining += 1;
+        }
+        assert_eq!(popped.into_inner() + remaining, 160_000);
+    }
+
+    #[test]
+    fn test_values_are_not_duplicated_or_lost() {
+        let value = Arc::new(());
+        let stack = EliminationStack::with_config(1, 1000);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..10000 {
+                        stack.push(value.clone());
+                        drop(stack.pop());
+                    }
+                });
+            }
+        });
+        drop(stack);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+}
@@ -0,0 +1,192 @@
+//! Epoch-based reclamation.
+//!
+//! Threads pin themselves to the global epoch while they access shared nodes.
+//! Retired nodes are tagged with the epoch they were retired in and go into the
+//! retiring thread's bag. The global epoch only advances once every pinned thread
+//! has seen the current one, so a node retired in epoch `e` can no longer be
+//! observed by anyone once the global epoch has reached `e + 2`.
+
+use crate::{
+    reclaim::{Guard, Link, Reclaim},
+    records::RecordList,
+    sync::{fence, AtomicBool, AtomicUsize, Ordering},
+};
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+
+/// How many retired pointers a bag collects before it tries to advance the epoch.
+const COLLECT_THRESHOLD: usize = 64;
+
+/// Participant state when it is not pinned, pinned states are `epoch << 1 | 1`.
+const UNPINNED: usize = 0;
+
+/// A participant holds the pin state and the bag of whichever thread has claimed
+/// it.
+struct Participant {
+    state: AtomicUsize,
+    active: AtomicBool,
+    // only touched by the thread that currently holds `active`
+    bag: UnsafeCell<Vec<(usize, *mut u8)>>,
+}
+
+/// An epoch-based reclamation domain. Like [`HazardPointers`](crate::hazard::HazardPointers)
+/// every pointer retired into it must be freeable by the same `free` callback.
+pub struct Epoch {
+    epoch: AtomicUsize,
+    participants: RecordList<Participant>,
+}
+
+impl Epoch {
+    pub fn new() -> Self {
+        Self {
+            epoch: AtomicUsize::new(0),
+            participants: RecordList::new(),
+        }
+    }
+
+    /// Pins the calling thread to the current epoch until the guard is dropped.
+    pub fn pin(&self) -> EpochGuard<'_> {
+        let participant = self.acquire();
+        let epoch = self.epoch.load(Ordering::Relaxed);
+        participant.state.store(epoch << 1 | 1, Ordering::Relaxed);
+        // the pin has to be visible before we read any shared pointer
+        fence(Ordering::SeqCst);
+        EpochGuard {
+            domain: self,
+            participant,
+        }
+    }
+
+    fn acquire(&self) -> &Participant {
+        self.participants.claim(
+            |participant| {
+                !participant.active.load(Ordering::Relaxed)
+                    && participant
+                        .active
+                        .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                        .is_ok()
+            },
+            || Participant {
+                state: AtomicUsize::new(UNPINNED),
+                active: AtomicBool::new(true),
+                bag: UnsafeCell::new(Vec::new()),
+            },
+        )
+    }
+
+    /// Moves the global epoch forward if every pinned participant has seen it and
+    /// returns the epoch that is current afterwards.
+    fn try_advance(&self) -> usize {
+        // pairs with the fence in `pin`: the unlinks before the retire may be
+        // relaxed, and either a thread pinned after them cannot see the nodes or
+        // we see its pin
+        fence(Ordering::SeqCst);
+        let epoch = self.epoch.load(Ordering::SeqCst);
+        for participant in self.participants.iter() {
+            let state = participant.state.load(Ordering::SeqCst);
+            if state & 1 == 1 && state >> 1 != epoch {
+                return epoch;
+            }
+        }
+        match self
+            .epoch
+            .compare_exchange(epoch, epoch + 1, Ordering::SeqCst, Ordering::SeqCst)
+        {
+            Ok(_) => epoch + 1,
+            Err(actual) => actual,
+        }
+    }
+}
+
+impl Default for Epoch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl Reclaim for Epoch {
+    type Guard<'a> = EpochGuard<'a>;
+
+    fn guard(&self) -> EpochGuard<'_> {
+        self.pin()
+    }
+
+    fn reclaim_all(&mut self, mut free: impl FnMut(*mut u8)) {
+        for participant in self.participants.iter_mut() {
+            for (_, ptr) in participant.bag.get_mut().drain(..) {
+                free(ptr);
+            }
+        }
+    }
+}
+
+/// Keeps the calling thread pinned. Every pointer loaded while the guard is alive
+/// stays valid until it is dropped.
+pub struct EpochGuard<'a> {
+    domain: &'a Epoch,
+    participant: &'a Participant,
+}
+
+impl Guard for EpochGuard<'_> {
+    fn protect<L: Link>(&mut self, _slot: usize, src: &L) -> L::Ptr {
+        src.load(Ordering::SeqCst)
+    }
+
+    unsafe fn retire(&mut self, ptr: *mut u8, mut free: impl FnMut(*mut u8)) {
+        let bag = unsafe { &mut *self.participant.bag.get() };
+        // order the unlink before the epoch load, as in `try_advance`
+        fence(Ordering::SeqCst);
+        bag.push((self.domain.epoch.load(Ordering::SeqCst), ptr));
+        if bag.len() >= COLLECT_THRESHOLD {
+            let epoch = self.domain.try_advance();
+            bag.retain(|&(retired_in, ptr)| {
+                if retired_in + 2 > epoch {
+                    return true;
+                }
+                free(ptr);
+                false
+            });
+        }
+    }
+}
+
+impl Drop for EpochGuard<'_> {
+    fn drop(&mut self) {
+        self.participant.state.store(UNPINNED, Ordering::Release);
+        self.participant.active.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(all(test, not(feature = "loom")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_epoch_waits_for_pinned_threads() {
+        let domain = Epoch::new();
+        let reader = domain.pin();
+
+        // the writer runs in the same epoch, so it can advance it once but not twice
+        let mut freed = Vec::new();
+        let mut writer = domain.pin();
+        for _ in 0..COLLECT_THRESHOLD * 4 {
+            let ptr = Box::into_raw(Box::new(0_u64));
+            unsafe { writer.retire(ptr.cast(), |p| freed.push(p)) };
+        }
+        assert!(freed.is_empty());
+        assert_eq!(domain.epoch.load(Ordering::SeqCst), 1);
+
+        drop(reader);
+        drop(writer);
+        let mut writer = domain.pin();
+        for _ in 0..COLLECT_THRESHOLD * 4 {
+            let ptr = Box::into_raw(Box::new(0_u64));
+            unsafe { writer.retire(ptr.cast(), |p| freed.push(p)) };
+        }
+        assert!(!freed.is_empty());
+
+        for ptr in freed {
+            drop(unsafe { Box::from_raw(ptr.cast::<u64>()) });
+        }
+    }
+}
@@ -0,0 +1,202 @@
+//! Hazard pointers.
+//!
+//! Before dereferencing a shared node a thread publishes its address in one of its
+//! hazard slots and re-checks that the node is still reachable. Unlinked nodes are
+//! not freed straight away but retired; once enough of them pile up the retiring
+//! thread scans every hazard slot and frees the nodes nobody is protecting.
+
+use crate::{
+    reclaim::{Guard, Link, Reclaim},
+    records::RecordList,
+    sync::{fence, AtomicBool, AtomicPtr, Ordering},
+};
+use alloc::vec::Vec;
+use core::{cell::UnsafeCell, ptr};
+
+/// Number of hazard slots a single guard can use at the same time.
+pub const SLOTS: usize = 2;
+
+/// How many retired pointers a record collects before it scans the hazards.
+const SCAN_THRESHOLD: usize = 64;
+
+/// A record holds the hazard slots and the retire list of whichever thread has
+/// claimed it.
+struct Record {
+    hazards: [AtomicPtr<u8>; SLOTS],
+    active: AtomicBool,
+    // only touched by the thread that currently holds `active`
+    retired: UnsafeCell<Vec<*mut u8>>,
+}
+
+/// A hazard pointer domain. Every pointer retired into a domain must be freeable by
+/// the same `free` callback, so in practice a domain belongs to one data structure.
+pub struct HazardPointers {
+    records: RecordList<Record>,
+}
+
+impl HazardPointers {
+    pub fn new() -> Self {
+        Self {
+            records: RecordList::new(),
+        }
+    }
+
+    /// Claims a record for the calling thread. The record is released when the
+    /// guard is dropped.
+    pub fn guard(&self) -> HazardGuard<'_> {
+        HazardGuard {
+            domain: self,
+            record: self.acquire(),
+        }
+    }
+
+    fn acquire(&self) -> &Record {
+        self.records.claim(
+            |record| {
+                !record.active.load(Ordering::Relaxed)
+                    && record
+                        .active
+                        .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                        .is_ok()
+            },
+            || Record {
+                hazards: core::array::from_fn(|_| AtomicPtr::new(ptr::null_mut())),
+                active: AtomicBool::new(true),
+                retired: UnsafeCell::new(Vec::new()),
+            },
+        )
+    }
+
+    /// Frees every pointer in `retired` that is not currently protected by a hazard.
+    fn scan(&self, retired: &mut Vec<*mut u8>, mut free: impl FnMut(*mut u8)) {
+        // pairs with the re-check in `protect`: the structure may have unlinked the
+        // pointers with a relaxed RMW, and either the reader sees them unlinked or
+        // we see its hazard
+        fence(Ordering::SeqCst);
+        let mut protected = Vec::new();
+        for record in self.records.iter() {
+            for hazard in &record.hazards {
+                let ptr = hazard.load(Ordering::SeqCst);
+                if !ptr.is_null() {
+                    protected.push(ptr);
+                }
+            }
+        }
+        protected.sort_unstable();
+
+        retired.retain(|&ptr| {
+            if protected.binary_search(&ptr).is_ok() {
+                return true;
+            }
+            free(ptr);
+            false
+        });
+    }
+}
+
+impl Default for HazardPointers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl Reclaim for HazardPointers {
+    type Guard<'a> = HazardGuard<'a>;
+
+    fn guard(&self) -> HazardGuard<'_> {
+        self.guard()
+    }
+
+    fn reclaim_all(&mut self, mut free: impl FnMut(*mut u8)) {
+        for record in self.records.iter_mut() {
+            for ptr in record.retired.get_mut().drain(..) {
+                free(ptr);
+            }
+        }
+    }
+}
+
+/// Gives access to the hazard slots and retire list of one record.
+pub struct HazardGuard<'a> {
+    domain: &'a HazardPointers,
+    record: &'a Record,
+}
+
+impl Guard for HazardGuard<'_> {
+    /// The pointer stays safe to dereference until the slot is reused or the guard
+    /// is dropped.
+    fn protect<L: Link>(&mut self, slot: usize, src: &L) -> L::Ptr {
+        let hazard = &self.record.hazards[slot];
+        let mut ptr = src.load(Ordering::SeqCst);
+        loop {
+            hazard.store(L::address(ptr), Ordering::SeqCst);
+            // the node might have been unlinked before the hazard became visible,
+            // only trust it if `src` still points to it
+            let current = src.load(Ordering::SeqCst);
+            if current == ptr {
+                return ptr;
+            }
+            ptr = current;
+        }
+    }
+
+    unsafe fn retire(&mut self, ptr: *mut u8, free: impl FnMut(*mut u8)) {
+        let retired = unsafe { &mut *self.record.retired.get() };
+        retired.push(ptr);
+        if retired.len() >= SCAN_THRESHOLD {
+            self.domain.scan(retired, free);
+        }
+    }
+}
+
+impl Drop for HazardGuard<'_> {
+    fn drop(&mut self) {
+        for hazard in &self.record.hazards {
+            hazard.store(ptr::null_mut(), Ordering::Release);
+        }
+        self.record.active.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(all(test, not(feature = "loom")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_protected_pointer_is_not_freed() {
+        let domain = HazardPointers::new();
+        let src = AtomicPtr::new(Box::into_raw(Box::new(7_u64)));
+
+        let mut reader = domain.guard();
+        let protected = reader.protect(0, &src);
+
+        let mut freed = Vec::new();
+        let mut writer = domain.guard();
+        unsafe { writer.retire(protected.cast(), |p| freed.push(p)) };
+        for _ in 1..SCAN_THRESHOLD {
+            let ptr = Box::into_raw(Box::new(0_u64));
+            unsafe { writer.retire(ptr.cast(), |p| freed.push(p)) };
+        }
+        assert_eq!(freed.len(), SCAN_THRESHOLD - 1);
+        assert!(!freed.contains(&protected.cast()));
+
+        drop(reader);
+        for _ in 1..SCAN_THRESHOLD {
+            let ptr = Box::into_raw(Box::new(0_u64));
+            unsafe { writer.retire(ptr.cast(), |p| freed.push(p)) };
+        }
+        assert!(freed.contains(&protected.cast()));
+
+        for ptr in freed {
+            drop(unsafe { Box::from_raw(ptr.cast::<u64>()) });
+        }
+    }
+
+    #[test]
+    fn test_records_are_reused() {
+        let domain = HazardPointers::new();
+        let first: *const Record = domain.guard().record;
+        let second: *const Record = domain.guard().record;
+        assert_eq!(first, second);
+    }
+}
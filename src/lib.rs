@@ -0,0 +1,44 @@
+//! Lock-free data structures.
+//!
+//! The node-based structures own a memory reclamation domain (see [`reclaim`]) so
+//! that nodes unlinked by one thread are only freed once no other thread can still
+//! read them.
+//!
+//! # Features
+//! - `std`, on by default, adds what needs threads: the blocking pops, the
+//!   `mpsc` channel and the `backoff::SpinThenYield` backoff. Without it the crate
+//!   only needs `core` and `alloc`.
+//! - `seqcst` makes the stack use `SeqCst` for every atomic access.
+//! - `loom` builds the node-based structures on loom's atomics for the model
+//!   checked tests in `tests/loom.rs`. Those atomics only work inside a loom
+//!   model, so run nothing but that test file with it.
+
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+pub mod allocator;
+mod array_queue;
+pub mod backoff;
+mod cache_padded;
+mod elimination;
+pub mod epoch;
+pub mod hazard;
+pub mod linearizability;
+#[cfg(feature = "std")]
+pub mod mpsc;
+mod queue;
+pub mod reclaim;
+mod records;
+pub mod spsc;
+pub mod stack;
+mod sync;
+pub mod tagged;
+mod waiters;
+
+pub use array_queue::ArrayQueue;
+pub use elimination::EliminationStack;
+pub use epoch::Epoch;
+pub use hazard::HazardPointers;
+pub use queue::LockFreeQueue;
+pub use stack::LockFreeStack;
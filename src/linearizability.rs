@@ -0,0 +1,457 @@
+//! Linearizability checking for recorded concurrent histories.
+//!
+//! Threads record every operation they run on a structure through a shared
+//! [`Recorder`], which stamps the invocation and the response with a logical clock.
+//! [`linearize`] then searches for an order of the operations that respects those
+//! stamps and that a sequential [`Specification`] agrees with (Wing & Gong, with
+//! Lowe's memoization of visited states).
+//!
+//! ```
+//! use lock_free::linearizability::{linearize, Recorder, SequentialStack, StackOp};
+//! use lock_free::LockFreeStack;
+//!
+//! let stack = LockFreeStack::new();
+//! let recorder = Recorder::new();
+//! let history = std::thread::scope(|s| {
+//!     let handles: Vec<_> = (0..2)
+//!         .map(|t| {
+//!             let (stack, recorder) = (&stack, &recorder);
+//!             s.spawn(move || {
+//!                 let mut log = recorder.log();
+//!                 log.record(StackOp::Push(t), |_| {
+//!                     stack.push(t);
+//!                     None
+//!                 });
+//!                 log.record(StackOp::Pop, |_| stack.pop());
+//!                 log.into_entries()
+//!             })
+//!         })
+//!         .collect();
+//!     handles.into_iter().flat_map(|h| h.join().unwrap()).collect::<Vec<_>>()
+//! });
+//! assert!(linearize(SequentialStack::default(), &history).is_some());
+//! ```
+
+use alloc::{
+    collections::{BTreeSet, VecDeque},
+    vec,
+    vec::Vec,
+};
+use core::{
+    hash::{Hash, Hasher},
+    mem,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// How many linearized entries the search goes between keeping a copy of the
+/// state. Backtracking past the others replays them from the last copy.
+const CHECKPOINT_INTERVAL: usize = 64;
+
+/// The sequential behavior a concurrent structure is checked against. The search
+/// remembers the states it has been in by their hash.
+pub trait Specification: Clone + Hash {
+    type Op;
+    type Ret: PartialEq;
+
+    /// Runs `op` on the sequential structure and returns what it would return.
+    fn apply(&mut self, op: &Self::Op) -> Self::Ret;
+}
+
+/// A completed operation and the logical times it was invoked and returned at.
+#[derive(Clone, Debug)]
+pub struct Entry<Op, Ret> {
+    pub op: Op,
+    pub ret: Ret,
+    pub invoked: usize,
+    pub returned: usize,
+}
+
+/// The clock shared by every thread recording into the same history.
+#[derive(Debug, Default)]
+pub struct Recorder {
+    clock: AtomicUsize,
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Opens a log for the calling thread.
+    pub fn log<Op, Ret>(&self) -> Log<'_, Op, Ret> {
+        Log {
+            clock: &self.clock,
+            entries: Vec::new(),
+        }
+    }
+
+    fn tick(clock: &AtomicUsize) -> usize {
+        // SeqCst so that an operation stamped as returned before another one was
+        // invoked really happened before it
+        clock.fetch_add(1, Ordering::SeqCst)
+    }
+}
+
+/// The operations one thread has run.
+pub struct Log<'a, Op, Ret> {
+    clock: &'a AtomicUsize,
+    entries: Vec<Entry<Op, Ret>>,
+}
+
+impl<Op, Ret: Clone> Log<'_, Op, Ret> {
+    /// Runs `op` through `run` and records it with what it returned.
+    pub fn record(&mut self, op: Op, run: impl FnOnce(&Op) -> Ret) -> Ret {
+        let invoked = Recorder::tick(self.clock);
+        let ret = run(&op);
+        let returned = Recorder::tick(self.clock);
+        self.entries.push(Entry {
+            op,
+            ret: ret.clone(),
+            invoked,
+            returned,
+        });
+        ret
+    }
+
+    pub fn into_entries(self) -> Vec<Entry<Op, Ret>> {
+        self.entries
+    }
+}
+
+/// Looks for a linearization of `history` starting from `initial`. Returns the
+/// indices of the entries in the order they take effect, or `None` if the history
+/// is not linearizable.
+pub fn linearize<S: Specification>(
+    initial: S,
+    history: &[Entry<S::Op, S::Ret>],
+) -> Option<Vec<usize>> {
+    let mut by_invoked = Pending::new(history, |entry| entry.invoked);
+    let mut by_returned = Pending::new(history, |entry| entry.returned);
+    let mut linearized = Bitset::new(history.len());
+    // states already reached with the same set of linearized entries, searching
+    // from them again cannot succeed where it failed before. Only a hash of each
+    // state is kept, a collision could prune a branch that would have succeeded
+    // but is as unlikely as any with 64 bits.
+    let mut visited = BTreeSet::new();
+    // the entries linearized so far, depth first
+    let mut frames: Vec<Frame<S>> = Vec::new();
+    let mut state = initial;
+    // the next entry to try at the current depth
+    let mut next = by_invoked.first();
+
+    while frames.len() < history.len() {
+        // an entry can only go next if it was invoked before every pending entry
+        // returned, otherwise that entry would have to take effect first
+        let deadline = by_returned
+            .first()
+            .map_or(usize::MAX, |i| history[i].returned);
+        let Some(i) = next.filter(|&i| history[i].invoked < deadline) else {
+            // nothing fits at this depth, take back the last entry and try the
+            // ones after it instead
+            let frame = frames.pop()?;
+            linearized.remove(frame.entry);
+            by_invoked.restore(frame.entry);
+            by_returned.restore(frame.entry);
+            state = match frame.before {
+                Some(before) => before,
+                None => replay(&frames, history),
+            };
+            next = by_invoked.after(frame.entry);
+            continue;
+        };
+        next = by_invoked.after(i);
+
+        let entry = &history[i];
+        let mut after = state.clone();
+        if after.apply(&entry.op) != entry.ret {
+            continue;
+        }
+        linearized.insert(i);
+        if !visited.insert((hash(&after), linearized.clone())) {
+            linearized.remove(i);
+            continue;
+        }
+        by_invoked.remove(i);
+        by_returned.remove(i);
+        let before = mem::replace(&mut state, after);
+        frames.push(Frame {
+            entry: i,
+            before: frames
+                .len()
+                .is_multiple_of(CHECKPOINT_INTERVAL)
+                .then_some(before),
+        });
+        next = by_invoked.first();
+    }
+    Some(frames.into_iter().map(|frame| frame.entry).collect())
+}
+
+/// One linearized entry on the search stack.
+struct Frame<S> {
+    entry: usize,
+    // the state before the entry, on every `CHECKPOINT_INTERVAL`th frame
+    before: Option<S>,
+}
+
+/// Rebuilds the state after the entries in `frames` from the last checkpoint.
+fn replay<S: Specification>(frames: &[Frame<S>], history: &[Entry<S::Op, S::Ret>]) -> S {
+    let start = frames
+        .iter()
+        .rposition(|frame| frame.before.is_some())
+        .expect("the first frame keeps its state");
+    let mut state = frames[start].before.clone().unwrap();
+    for frame in &frames[start..] {
+        state.apply(&history[frame.entry].op);
+    }
+    state
+}
+
+/// The entries that are not linearized yet, as a doubly linked list sorted by one
+/// of their times. Entries leave and come back in stack order as the search goes
+/// down and backtracks, so each of them can be unlinked and relinked in place.
+struct Pending {
+    // the last slot is the list head
+    next: Vec<usize>,
+    prev: Vec<usize>,
+}
+
+impl Pending {
+    fn new<Op, Ret>(history: &[Entry<Op, Ret>], time: impl Fn(&Entry<Op, Ret>) -> usize) -> Self {
+        let head = history.len();
+        let mut order: Vec<_> = (0..head).collect();
+        order.sort_by_key(|&i| time(&history[i]));
+        let mut next = vec![head; head + 1];
+        let mut prev = vec![head; head + 1];
+        let mut last = head;
+        for i in order {
+            next[last] = i;
+            prev[i] = last;
+            last = i;
+        }
+        prev[head] = last;
+        Self { next, prev }
+    }
+
+    fn first(&self) -> Option<usize> {
+        self.after(self.next.len() - 1)
+    }
+
+    fn after(&self, i: usize) -> Option<usize> {
+        Some(self.next[i]).filter(|&next| next != self.next.len() - 1)
+    }
+
+    fn remove(&mut self, i: usize) {
+        let (prev, next) = (self.prev[i], self.next[i]);
+        self.next[prev] = next;
+        self.prev[next] = prev;
+    }
+
+    /// Puts back the entry removed last.
+    fn restore(&mut self, i: usize) {
+        let (prev, next) = (self.prev[i], self.next[i]);
+        self.next[prev] = i;
+        self.prev[next] = i;
+    }
+}
+
+fn hash<S: Hash>(state: &S) -> u64 {
+    let mut hasher = FxHasher(0);
+    state.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// The hasher rustc uses, `core` comes without one. It takes a word at a time,
+/// which keeps hashing a large state cheap.
+struct FxHasher(u64);
+
+impl FxHasher {
+    fn add(&mut self, word: u64) {
+        self.0 = (self.0.rotate_left(5) ^ word).wrapping_mul(0x517c_c1b7_2722_0a95);
+    }
+}
+
+impl Hasher for FxHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(8) {
+            let mut word = [0; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.add(u64::from_le_bytes(word));
+        }
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.add(n);
+    }
+
+    fn write_usize(&mut self, n: usize) {
+        self.add(n as u64);
+    }
+}
+
+/// The set of linearized entries.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Bitset(Vec<u64>);
+
+impl Bitset {
+    fn new(len: usize) -> Self {
+        Self(vec![0; len.div_ceil(64)])
+    }
+
+    fn insert(&mut self, i: usize) {
+        self.0[i / 64] |= 1 << (i % 64);
+    }
+
+    fn remove(&mut self, i: usize) {
+        self.0[i / 64] &= !(1 << (i % 64));
+    }
+}
+
+/// An operation on a stack, pushes return `None`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StackOp<T> {
+    Push(T),
+    Pop,
+}
+
+/// The specification of a LIFO stack.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SequentialStack<T>(Vec<T>);
+
+impl<T: Clone + Eq + Hash> Specification for SequentialStack<T> {
+    type Op = StackOp<T>;
+    type Ret = Option<T>;
+
+    fn apply(&mut self, op: &StackOp<T>) -> Option<T> {
+        match op {
+            StackOp::Push(value) => {
+                self.0.push(value.clone());
+                None
+            }
+            StackOp::Pop => self.0.pop(),
+        }
+    }
+}
+
+/// An operation on a queue, enqueues return `None`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum QueueOp<T> {
+    Enqueue(T),
+    Dequeue,
+}
+
+/// The specification of a FIFO queue.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SequentialQueue<T>(VecDeque<T>);
+
+impl<T: Clone + Eq + Hash> Specification for SequentialQueue<T> {
+    type Op = QueueOp<T>;
+    type Ret = Option<T>;
+
+    fn apply(&mut self, op: &QueueOp<T>) -> Option<T> {
+        match op {
+            QueueOp::Enqueue(value) => {
+                self.0.push_back(value.clone());
+                None
+            }
+            QueueOp::Dequeue => self.0.pop_front(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry<T>(
+        op: StackOp<T>,
+        ret: Option<T>,
+        invoked: usize,
+        returned: usize,
+    ) -> Entry<StackOp<T>, Option<T>> {
+        Entry {
+            op,
+            ret,
+            invoked,
+            returned,
+        }
+    }
+
+    #[test]
+    fn test_overlapping_operations_can_reorder() {
+        // the pop runs inside push(2), so it can take effect before it
+        let history = [
+            entry(StackOp::Push(1), None, 0, 1),
+            entry(StackOp::Push(2), None, 2, 5),
+            entry(StackOp::Pop, Some(1), 3, 4),
+        ];
+        let order = linearize(SequentialStack::default(), &history).unwrap();
+        assert_eq!(order, [0, 2, 1]);
+    }
+
+    #[test]
+    fn test_real_time_order_is_respected() {
+        // push(2) returned before the pop was invoked, so the pop must see 2 on top
+        let history = [
+            entry(StackOp::Push(1), None, 0, 1),
+            entry(StackOp::Push(2), None, 2, 3),
+            entry(StackOp::Pop, Some(1), 4, 5),
+        ];
+        assert_eq!(linearize(SequentialStack::default(), &history), None);
+    }
+
+    #[test]
+    fn test_value_popped_twice_is_rejected() {
+        let history = [
+            entry(StackOp::Push(1), None, 0, 1),
+            entry(StackOp::Pop, Some(1), 2, 4),
+            entry(StackOp::Pop, Some(1), 3, 5),
+        ];
+        assert_eq!(linearize(SequentialStack::default(), &history), None);
+    }
+
+    #[test]
+    fn test_long_history_backtracks_without_recursion() {
+        // sequential pushes and three overlapping pops at the end, far deeper than
+        // a recursive search could go on a test thread's stack
+        let n = 10_000;
+        let mut history: Vec<_> = (0..n)
+            .map(|i| entry(StackOp::Push(i), None, 2 * i, 2 * i + 1))
+            .collect();
+        history.extend((0..3).map(|k| entry(StackOp::Pop, Some(n - 3 + k), 2 * n, 2 * n + 1 + k)));
+        let order = linearize(SequentialStack::default(), &history).unwrap();
+        assert!(order[..n].iter().copied().eq(0..n));
+        assert_eq!(order[n..], [n + 2, n + 1, n]);
+
+        history[n].ret = Some(0);
+        assert_eq!(linearize(SequentialStack::default(), &history), None);
+    }
+
+    #[test]
+    fn test_queue_specification() {
+        let history = [
+            Entry {
+                op: QueueOp::Enqueue(1),
+                ret: None,
+                invoked: 0,
+                returned: 1,
+            },
+            Entry {
+                op: QueueOp::Enqueue(2),
+                ret: None,
+                invoked: 2,
+                returned: 3,
+            },
+            Entry {
+                op: QueueOp::Dequeue,
+                ret: Some(2),
+                invoked: 4,
+                returned: 5,
+            },
+        ];
+        assert_eq!(linearize(SequentialQueue::default(), &history), None);
+    }
+}
@@ -0,0 +1,396 @@
+//! A multi-producer single-consumer channel on top of [`LockFreeQueue`].
+//!
+//! [`channel`] returns a [`Sender`] that can be cloned for every producer and the
+//! one [`Receiver`]. Sending never blocks, the queue is unbounded. Receiving parks
+//! the thread while the queue is empty, until a message arrives or every sender is
+//! gone.
+//!
+//! ```
+//! use std::thread;
+//!
+//! let (sender, receiver) = lock_free::mpsc::channel();
+//! for i in 0..4 {
+//!     let sender = sender.clone();
+//!     thread::spawn(move || sender.send(i).unwrap());
+//! }
+//! drop(sender);
+//! let mut received: Vec<_> = receiver.iter().collect();
+//! received.sort();
+//! assert_eq!(received, [0, 1, 2, 3]);
+//! ```
+
+use crate::{
+    queue::LockFreeQueue,
+    sync::{AtomicBool, AtomicUsize, Ordering},
+    waiters::Waiters,
+};
+use std::{
+    cell::Cell,
+    error::Error,
+    fmt,
+    marker::PhantomData,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+struct Shared<T> {
+    queue: LockFreeQueue<T>,
+    senders: AtomicUsize,
+    receiver_alive: AtomicBool,
+    // the receiver, while it is parked
+    waiters: Waiters,
+}
+
+/// Creates a channel and returns its two ends.
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        queue: LockFreeQueue::new(),
+        senders: AtomicUsize::new(1),
+        receiver_alive: AtomicBool::new(true),
+        waiters: Waiters::new(),
+    });
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver {
+            shared,
+            _not_sync: PhantomData,
+        },
+    )
+}
+
+/// The sending end of a channel, clone it to get another one.
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Sends `value`, or returns it back if the receiver is gone.
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        if !self.shared.receiver_alive.load(Ordering::Relaxed) {
+            return Err(SendError(value));
+        }
+        self.shared.queue.enqueue(value);
+        self.shared.waiters.notify(1);
+        Ok(())
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        // release our sends to the receiver that sees the count drop to zero, SeqCst
+        // for `notify`
+        if self.shared.senders.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.shared.waiters.notify(1);
+        }
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender").finish_non_exhaustive()
+    }
+}
+
+/// The receiving end of a channel. Like the standard library's it can be sent to
+/// another thread but not shared between threads, there is only one consumer.
+///
+/// ```compile_fail
+/// fn assert_sync<S: Sync>() {}
+/// assert_sync::<lock_free::mpsc::Receiver<u8>>();
+/// ```
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    _not_sync: PhantomData<Cell<()>>,
+}
+
+impl<T> Receiver<T> {
+    /// Takes the oldest message without waiting.
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        if let Some(value) = self.shared.queue.dequeue() {
+            return Ok(value);
+        }
+        if self.shared.senders.load(Ordering::SeqCst) == 0 {
+            // the last sender may have sent right before it went away
+            return self
+                .shared
+                .queue
+                .dequeue()
+                .ok_or(TryRecvError::Disconnected);
+        }
+        Err(TryRecvError::Empty)
+    }
+
+    /// Takes the oldest message, parking the thread until one arrives. Fails once
+    /// the channel is empty and every sender is gone.
+    pub fn recv(&self) -> Result<T, RecvError> {
+        match self.recv_until(None) {
+            Ok(value) => Ok(value),
+            Err(_) => Err(RecvError),
+        }
+    }
+
+    /// Like [`recv`](Self::recv), but gives up once `timeout` has passed.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        // a deadline too far out to represent is as good as none
+        self.recv_until(Instant::now().checked_add(timeout))
+    }
+
+    fn recv_until(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        let received = self
+            .shared
+            .waiters
+            .wait_until(deadline, || match self.try_recv() {
+                Ok(value) => Some(Ok(value)),
+                Err(TryRecvError::Disconnected) => Some(Err(RecvTimeoutError::Disconnected)),
+                Err(TryRecvError::Empty) => None,
+            });
+        received.unwrap_or(Err(RecvTimeoutError::Timeout))
+    }
+
+    /// Returns an iterator that waits for messages until every sender is gone.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter { receiver: self }
+    }
+
+    /// Returns an iterator over the messages that have already arrived.
+    pub fn try_iter(&self) -> TryIter<'_, T> {
+        TryIter { receiver: self }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.receiver_alive.store(false, Ordering::Relaxed);
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receiver").finish_non_exhaustive()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Receiver<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<T> IntoIterator for Receiver<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter { receiver: self }
+    }
+}
+
+/// Waits for messages until every sender is gone, see [`Receiver::iter`].
+#[derive(Debug)]
+pub struct Iter<'a, T> {
+    receiver: &'a Receiver<T>,
+}
+
+impl<T> Iterator for Iter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.receiver.recv().ok()
+    }
+}
+
+/// Yields the messages that have already arrived, see [`Receiver::try_iter`].
+#[derive(Debug)]
+pub struct TryIter<'a, T> {
+    receiver: &'a Receiver<T>,
+}
+
+impl<T> Iterator for TryIter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+/// Waits for messages until every sender is gone, owning the receiver.
+#[derive(Debug)]
+pub struct IntoIter<T> {
+    receiver: Receiver<T>,
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.receiver.recv().ok()
+    }
+}
+
+/// Returned by [`Sender::send`] with the value that could not be sent because the
+/// receiver is gone.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SendError").finish_non_exhaustive()
+    }
+}
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("sending on a closed channel")
+    }
+}
+
+impl<T> Error for SendError<T> {}
+
+/// Returned by [`Receiver::recv`] once the channel is empty and every sender is
+/// gone.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RecvError;
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("receiving on an empty and disconnected channel")
+    }
+}
+
+impl Error for RecvError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TryRecvError {
+    /// No message has arrived yet.
+    Empty,
+    /// The channel is empty and every sender is gone.
+    Disconnected,
+}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryRecvError::Empty => f.write_str("receiving on an empty channel"),
+            TryRecvError::Disconnected => {
+                f.write_str("receiving on an empty and disconnected channel")
+            }
+        }
+    }
+}
+
+impl Error for TryRecvError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecvTimeoutError {
+    /// No message arrived before the timeout.
+    Timeout,
+    /// The channel is empty and every sender is gone.
+    Disconnected,
+}
+
+impl fmt::Display for RecvTimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecvTimeoutError::Timeout => f.write_str("timed out waiting on a channel"),
+            RecvTimeoutError::Disconnected => {
+                f.write_str("receiving on an empty and disconnected channel")
+            }
+        }
+    }
+}
+
+impl Error for RecvTimeoutError {}
+
+#[cfg(all(test, not(feature = "loom")))]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_messages_from_many_senders() {
+        let (sender, receiver) = channel();
+        thread::scope(|s| {
+            for i in 0..4 {
+                let sender = sender.clone();
+                s.spawn(move || {
+                    for j in 0..10000 {
+                        sender.send((i, j)).unwrap();
+                    }
+                });
+            }
+            drop(sender);
+            // messages from one sender arrive in the order they were sent
+            let mut next = [0; 4];
+            for (i, j) in &receiver {
+                assert_eq!(j, next[i]);
+                next[i] += 1;
+            }
+            assert_eq!(next, [10000; 4]);
+        });
+    }
+
+    #[test]
+    fn test_disconnect_after_last_sender() {
+        let (sender, receiver) = channel();
+        let other = sender.clone();
+        sender.send(1).unwrap();
+        drop(sender);
+        assert_eq!(receiver.try_recv(), Ok(1));
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Empty));
+        other.send(2).unwrap();
+        drop(other);
+        assert_eq!(receiver.recv(), Ok(2));
+        assert_eq!(receiver.recv(), Err(RecvError));
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn test_recv_wakes_on_send_and_disconnect() {
+        let (sender, receiver) = channel();
+        let consumer = thread::spawn(move || receiver.into_iter().collect::<Vec<_>>());
+        thread::sleep(Duration::from_millis(20));
+        sender.send(1).unwrap();
+        thread::sleep(Duration::from_millis(20));
+        drop(sender);
+        assert_eq!(consumer.join().unwrap(), [1]);
+    }
+
+    #[test]
+    fn test_recv_timeout() {
+        let (sender, receiver) = channel();
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_millis(20)),
+            Err(RecvTimeoutError::Timeout)
+        );
+        sender.send(1).unwrap();
+        assert_eq!(receiver.recv_timeout(Duration::from_millis(20)), Ok(1));
+        drop(sender);
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_millis(20)),
+            Err(RecvTimeoutError::Disconnected)
+        );
+    }
+
+    #[test]
+    fn test_send_after_receiver_is_gone() {
+        let (sender, receiver) = channel();
+        sender.send(1).unwrap();
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), [1]);
+        drop(receiver);
+        assert_eq!(sender.send(2), Err(SendError(2)));
+    }
+}
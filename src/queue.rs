@@ -0,0 +1,289 @@
+use crate::{
+    hazard::HazardPointers,
+    reclaim::{Guard, Reclaim},
+    sync::{AtomicPtr, Ordering},
+};
+use alloc::boxed::Box;
+use core::{mem::MaybeUninit, ptr};
+
+struct Node<T> {
+    // uninitialized in the sentinel, which is always the node `head` points to
+    data: MaybeUninit<T>,
+    next: AtomicPtr<Node<T>>,
+}
+
+impl<T> Node<T> {
+    fn alloc(data: MaybeUninit<T>) -> *mut Self {
+        Box::into_raw(Box::new(Node {
+            data,
+            next: AtomicPtr::new(ptr::null_mut()),
+        }))
+    }
+}
+
+/// An unbounded lock-free MPMC queue (Michael–Scott).
+///
+/// `head` always points to a sentinel node whose successor holds the oldest value.
+/// Dequeued sentinels are handed to the reclamation scheme `R`.
+///
+/// ```
+/// use lock_free::LockFreeQueue;
+///
+/// let queue = LockFreeQueue::new();
+/// queue.enqueue(1);
+/// queue.enqueue(2);
+/// assert_eq!(queue.dequeue(), Some(1));
+/// ```
+pub struct LockFreeQueue<T, R: Reclaim = HazardPointers> {
+    head: AtomicPtr<Node<T>>,
+    tail: AtomicPtr<Node<T>>,
+    reclaim: R,
+}
+
+// The nodes hang off `AtomicPtr`s, which would make the queue `Send` and `Sync`
+// whatever `T` is, so the bounds are spelled out. Sending the queue sends the
+// values in the nodes after the sentinel along with it.
+unsafe impl<T: Send, R: Reclaim> Send for LockFreeQueue<T, R> {}
+
+// A value is written before the CAS on `next` publishes its node, and read once by
+// the dequeue whose CAS makes that node the sentinel. A sentinel's data counts as
+// moved out and is never touched again, so each value passes from one thread to
+// one other and `T: Send` is enough.
+unsafe impl<T: Send, R: Reclaim> Sync for LockFreeQueue<T, R> {}
+
+impl<T> LockFreeQueue<T> {
+    /// Creates an empty queue that reclaims nodes with hazard pointers.
+    pub fn new() -> Self {
+        Self::with_reclaim(HazardPointers::new())
+    }
+}
+
+impl<T> Default for LockFreeQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, R: Reclaim> LockFreeQueue<T, R> {
+    /// Creates an empty queue that reclaims nodes through `reclaim`.
+    pub fn with_reclaim(reclaim: R) -> Self {
+        let sentinel = Node::alloc(MaybeUninit::uninit());
+        Self {
+            head: AtomicPtr::new(sentinel),
+            tail: AtomicPtr::new(sentinel),
+            reclaim,
+        }
+    }
+
+    pub fn enqueue(&self, data: T) {
+        let new_node = Node::alloc(MaybeUninit::new(data));
+        let mut guard = self.reclaim.guard();
+        loop {
+            let tail = guard.protect(0, &self.tail);
+            let next = unsafe { (*tail).next.load(Ordering::SeqCst) };
+            if !next.is_null() {
+                // tail is lagging behind, help move it forward before we retry
+                let _ = self
+                    .tail
+                    .compare_exchange(tail, next, Ordering::SeqCst, Ordering::SeqCst);
+                continue;
+            }
+            // link the node after the last one, tail is allowed to lag until the swing below
+            if unsafe { &(*tail).next }
+                .compare_exchange_weak(next, new_node, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+            {
+                let _ =
+                    self.tail
+                        .compare_exchange(tail, new_node, Ordering::SeqCst, Ordering::SeqCst);
+                return;
+            }
+        }
+    }
+
+    pub fn dequeue(&self) -> Option<T> {
+        let mut guard = self.reclaim.guard();
+        loop {
+            let head = guard.protect(0, &self.head);
+            let next = guard.protect(1, unsafe { &(*head).next });
+            // `next` is only known to be alive if `head` was still linked when we protected it
+            if self.head.load(Ordering::SeqCst) != head {
+                continue;
+            }
+            if next.is_null() {
+                return None;
+            }
+            let tail = self.tail.load(Ordering::SeqCst);
+            if head == tail {
+                // never let head overtake tail, help the enqueuer finish first
+                let _ = self
+                    .tail
+                    .compare_exchange(tail, next, Ordering::SeqCst, Ordering::SeqCst);
+                continue;
+            }
+            if self
+                .head
+                .compare_exchange_weak(head, next, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+            {
+                // `next` is the new sentinel, only the thread that swung head may take its data
+                let data = unsafe { (*next).data.assume_init_read() };
+                unsafe { guard.retire(head.cast(), free_node::<T>) };
+                return Some(data);
+            }
+        }
+    }
+
+    /// Returns `true` if the queue had no elements at the moment it was checked.
+    pub fn is_empty(&self) -> bool {
+        let mut guard = self.reclaim.guard();
+        let head = guard.protect(0, &self.head);
+        unsafe { (*head).next.load(Ordering::SeqCst) }.is_null()
+    }
+}
+
+impl<T, R: Reclaim> Drop for LockFreeQueue<T, R> {
+    fn drop(&mut self) {
+        let sentinel = self.head.load(Ordering::Relaxed);
+        let mut current = unsafe { Box::from_raw(sentinel) }
+            .next
+            .load(Ordering::Relaxed);
+        while !current.is_null() {
+            let mut node = unsafe { Box::from_raw(current) };
+            unsafe { node.data.assume_init_drop() };
+            current = node.next.load(Ordering::Relaxed);
+        }
+        self.reclaim.reclaim_all(free_node::<T>);
+    }
+}
+
+/// Deallocates a node without touching its data
+fn free_node<T>(node: *mut u8) {
+    drop(unsafe { Box::from_raw(node.cast::<Node<T>>()) });
+}
+
+#[cfg(all(test, not(feature = "loom")))]
+mod tests {
+    use super::*;
+    use crate::{
+        epoch::Epoch,
+        linearizability::{linearize, QueueOp, Recorder, SequentialQueue},
+    };
+    use std::{
+        sync::{atomic::AtomicUsize, Arc},
+        thread,
+    };
+
+    #[test]
+    fn test_enqueue() {
+        let queue = LockFreeQueue::new();
+        thread::scope(|s| {
+            for i in 0..10 {
+                let queue = &queue;
+                s.spawn(move || {
+                    for _ in 0..100000 {
+                        queue.enqueue(i);
+                    }
+                });
+            }
+        });
+        let mut count = 0;
+        while queue.dequeue().is_some() {
+            count += 1;
+        }
+        assert_eq!(count, 1_000_000)
+    }
+
+    #[test]
+    fn test_dequeue() {
+        let queue = LockFreeQueue::new();
+        for i in 0..100000 {
+            queue.enqueue(i);
+        }
+        thread::scope(|s| {
+            for _ in 0..10 {
+                s.spawn(|| {
+                    for _ in 0..100000 {
+                        let _ = queue.dequeue();
+                    }
+                });
+            }
+        });
+        assert!(queue.is_empty())
+    }
+
+    #[test]
+    fn test_fifo_per_producer() {
+        let queue = LockFreeQueue::with_reclaim(Epoch::new());
+        thread::scope(|s| {
+            for producer in 0..4 {
+                let queue = &queue;
+                s.spawn(move || {
+                    for i in 0..10000 {
+                        queue.enqueue((producer, i));
+                    }
+                });
+            }
+            for _ in 0..4 {
+                s.spawn(|| {
+                    let mut last = [-1; 4];
+                    for _ in 0..10000 {
+                        if let Some((producer, i)) = queue.dequeue() {
+                            assert!(i > last[producer]);
+                            last[producer] = i;
+                        }
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn test_history_is_linearizable() {
+        let queue = LockFreeQueue::new();
+        let recorder = Recorder::new();
+        let history: Vec<_> = thread::scope(|s| {
+            let handles: Vec<_> = (0..4)
+                .map(|i| {
+                    let queue = &queue;
+                    let recorder = &recorder;
+                    s.spawn(move || {
+                        let mut log = recorder.log();
+                        for j in 0..50 {
+                            if j % 2 == 1 {
+                                log.record(QueueOp::Dequeue, |_| queue.dequeue());
+                            } else {
+                                let value = i * 50 + j;
+                                log.record(QueueOp::Enqueue(value), |_| {
+                                    queue.enqueue(value);
+                                    None
+                                });
+                            }
+                        }
+                        log.into_entries()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|h| h.join().unwrap())
+                .collect()
+        });
+        assert!(linearize(SequentialQueue::default(), &history).is_some());
+    }
+
+    #[test]
+    fn test_drop_remaining_values() {
+        let value = Arc::new(AtomicUsize::new(0));
+        let queue = LockFreeQueue::new();
+        for _ in 0..1000 {
+            queue.enqueue(value.clone());
+        }
+        for _ in 0..500 {
+            queue.dequeue().unwrap();
+        }
+        assert_eq!(Arc::strong_count(&value), 501);
+        drop(queue);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+}
@@ -0,0 +1,63 @@
+//! Memory reclamation schemes the lock-free structures can be built on.
+//!
+//! A structure owns one reclamation domain. Threads open a guard before reading
+//! shared nodes, and nodes that have been unlinked are retired through the guard
+//! so the domain can free them once no guard can still observe them.
+
+use crate::sync::{AtomicPtr, Ordering};
+
+/// A memory reclamation scheme.
+///
+/// # Safety
+/// A pointer returned by [`Guard::protect`] must stay valid until the guard is
+/// dropped or the slot is protected again, even if it is retired in the meantime.
+pub unsafe trait Reclaim: Send + Sync {
+    type Guard<'a>: Guard
+    where
+        Self: 'a;
+
+    /// Opens a guard for the calling thread.
+    fn guard(&self) -> Self::Guard<'_>;
+
+    /// Frees every pointer still retired into the domain. Taking `&mut self` means
+    /// no guard can be alive anymore.
+    fn reclaim_all(&mut self, free: impl FnMut(*mut u8));
+}
+
+/// Per-thread access to a reclamation domain.
+pub trait Guard {
+    /// Loads `src` with at least `Acquire` ordering and protects the loaded pointer
+    /// in `slot`.
+    fn protect<L: Link>(&mut self, slot: usize, src: &L) -> L::Ptr;
+
+    /// Hands an unlinked pointer over to the domain. `free` may be called with any
+    /// pointer retired into the same domain that is safe to free. The unlink does
+    /// not need to be `SeqCst`, the domain fences before it looks for readers.
+    ///
+    /// # Safety
+    /// `ptr` must be unreachable for threads that have not protected it yet and must
+    /// not be retired twice.
+    unsafe fn retire(&mut self, ptr: *mut u8, free: impl FnMut(*mut u8));
+}
+
+/// An atomic location holding a pointer to a shared node.
+pub trait Link {
+    type Ptr: Copy + Eq;
+
+    fn load(&self, order: Ordering) -> Self::Ptr;
+
+    /// The address of the node `ptr` points to.
+    fn address(ptr: Self::Ptr) -> *mut u8;
+}
+
+impl<T> Link for AtomicPtr<T> {
+    type Ptr = *mut T;
+
+    fn load(&self, order: Ordering) -> *mut T {
+        self.load(order)
+    }
+
+    fn address(ptr: *mut T) -> *mut u8 {
+        ptr.cast()
+    }
+}
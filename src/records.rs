@@ -0,0 +1,83 @@
+//! The list of records the reclamation domains and the waiter list hand out to
+//! threads.
+//!
+//! A thread claims a record some other thread has released, or adds a new one if
+//! every record is busy. Records are never unlinked, so the list can be walked
+//! without protection and a claimed record stays valid as long as the list.
+
+use crate::sync::{AtomicPtr, Ordering};
+use alloc::boxed::Box;
+use core::{iter, ptr};
+
+struct Node<T> {
+    record: T,
+    next: *mut Node<T>,
+}
+
+pub(crate) struct RecordList<T> {
+    head: AtomicPtr<Node<T>>,
+}
+
+impl<T> RecordList<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            head: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    /// Returns the first record `try_claim` succeeds on, or adds the one `new`
+    /// makes, which has to be claimed already.
+    pub(crate) fn claim(&self, try_claim: impl Fn(&T) -> bool, new: impl FnOnce() -> T) -> &T {
+        // try to reuse a record some other thread has released
+        if let Some(record) = self.iter().find(|record| try_claim(record)) {
+            return record;
+        }
+
+        // every record is busy, add a new one
+        let new_node = Box::into_raw(Box::new(Node {
+            record: new(),
+            next: ptr::null_mut(),
+        }));
+        loop {
+            let head = self.head.load(Ordering::Acquire);
+            unsafe {
+                (*new_node).next = head;
+            }
+            if self
+                .head
+                .compare_exchange_weak(head, new_node, Ordering::Release, Ordering::Relaxed)
+                .is_ok()
+            {
+                return unsafe { &(*new_node).record };
+            }
+        }
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &T> {
+        let mut current = self.head.load(Ordering::Acquire);
+        iter::from_fn(move || {
+            let node = unsafe { current.as_ref()? };
+            current = node.next;
+            Some(&node.record)
+        })
+    }
+
+    pub(crate) fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        let mut current = self.head.load(Ordering::Relaxed);
+        iter::from_fn(move || {
+            let node = unsafe { current.as_mut()? };
+            current = node.next;
+            Some(&mut node.record)
+        })
+    }
+}
+
+impl<T> Drop for RecordList<T> {
+    fn drop(&mut self) {
+        let mut current = self.head.load(Ordering::Relaxed);
+        while !current.is_null() {
+            let node = unsafe { Box::from_raw(current) };
+            current = node.next;
+        }
+    }
+}
@@ -0,0 +1,316 @@
+//! A wait-free single-producer single-consumer ring buffer.
+//!
+//! [`channel`] splits the buffer into a [`Producer`] and a [`Consumer`]. Each handle
+//! owns one end, so neither side ever needs a CAS: every operation is a couple of
+//! loads and one release store. Each side also caches the other side's index and
+//! only reloads it when the buffer looks full (or empty), which keeps the shared
+//! cache lines quiet.
+//!
+//! ```
+//! let (mut producer, mut consumer) = lock_free::spsc::channel(4);
+//! std::thread::spawn(move || {
+//!     for i in 0..100 {
+//!         while producer.push(i).is_err() {}
+//!     }
+//! });
+//! for i in 0..100 {
+//!     loop {
+//!         if let Some(value) = consumer.pop() {
+//!             assert_eq!(value, i);
+//!             break;
+//!         }
+//!     }
+//! }
+//! ```
+
+use crate::cache_padded::CachePadded;
+use alloc::{boxed::Box, sync::Arc};
+use core::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+// Positions run from 0 to 2 * capacity so that a full buffer (`tail - head ==
+// capacity`) can be told apart from an empty one (`tail == head`).
+struct Inner<T> {
+    head: CachePadded<AtomicUsize>,
+    tail: CachePadded<AtomicUsize>,
+    buffer: Box<[UnsafeCell<MaybeUninit<T>>]>,
+}
+
+impl<T> Inner<T> {
+    fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
+
+    fn next(&self, position: usize) -> usize {
+        if position + 1 == 2 * self.capacity() {
+            0
+        } else {
+            position + 1
+        }
+    }
+
+    fn distance(&self, head: usize, tail: usize) -> usize {
+        if tail >= head {
+            tail - head
+        } else {
+            tail + 2 * self.capacity() - head
+        }
+    }
+
+    fn slot(&self, position: usize) -> *mut MaybeUninit<T> {
+        let index = if position < self.capacity() {
+            position
+        } else {
+            position - self.capacity()
+        };
+        self.buffer[index].get()
+    }
+}
+
+impl<T> Drop for Inner<T> {
+    fn drop(&mut self) {
+        let mut head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Relaxed);
+        while head != tail {
+            unsafe { (*self.slot(head)).assume_init_drop() };
+            head = self.next(head);
+        }
+    }
+}
+
+/// Creates a ring buffer that holds at most `capacity` values and returns its two
+/// ends.
+///
+/// # Panics
+/// If `capacity` is zero.
+pub fn channel<T>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    assert!(capacity > 0, "capacity must be non-zero");
+    let inner = Arc::new(Inner {
+        head: CachePadded::new(AtomicUsize::new(0)),
+        tail: CachePadded::new(AtomicUsize::new(0)),
+        buffer: (0..capacity)
+            .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+            .collect(),
+    });
+    let producer = Producer {
+        inner: inner.clone(),
+        tail: 0,
+        cached_head: 0,
+    };
+    let consumer = Consumer {
+        inner,
+        head: 0,
+        cached_tail: 0,
+    };
+    (producer, consumer)
+}
+
+/// The writing end of an SPSC ring buffer.
+pub struct Producer<T> {
+    inner: Arc<Inner<T>>,
+    tail: usize,
+    cached_head: usize,
+}
+
+// Only the producer writes slots between `head` and `tail`, and there is exactly
+// one producer, so it can move to another thread as long as the values can.
+unsafe impl<T: Send> Send for Producer<T> {}
+
+impl<T> Producer<T> {
+    /// Number of free slots, reloading the consumer's index only if the cached one
+    /// says there are fewer than `wanted`.
+    fn free_slots(&mut self, wanted: usize) -> usize {
+        let capacity = self.inner.capacity();
+        let mut free = capacity - self.inner.distance(self.cached_head, self.tail);
+        if free < wanted {
+            self.cached_head = self.inner.head.load(Ordering::Acquire);
+            free = capacity - self.inner.distance(self.cached_head, self.tail);
+        }
+        free
+    }
+
+    /// Pushes `value`, or returns it back if the buffer is full.
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        if self.free_slots(1) == 0 {
+            return Err(value);
+        }
+        unsafe { (*self.inner.slot(self.tail)).write(value) };
+        self.tail = self.inner.next(self.tail);
+        self.inner.tail.store(self.tail, Ordering::Release);
+        Ok(())
+    }
+
+    /// Copies as many values from `values` as fit and returns how many were pushed.
+    /// The consumer sees all of them at once.
+    pub fn push_slice(&mut self, values: &[T]) -> usize
+    where
+        T: Copy,
+    {
+        let count = self.free_slots(values.len()).min(values.len());
+        for &value in &values[..count] {
+            unsafe { (*self.inner.slot(self.tail)).write(value) };
+            self.tail = self.inner.next(self.tail);
+        }
+        self.inner.tail.store(self.tail, Ordering::Release);
+        count
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+
+    /// The number of values in the buffer, the consumer may have taken some since.
+    pub fn len(&self) -> usize {
+        let head = self.inner.head.load(Ordering::Acquire);
+        self.inner.distance(head, self.tail)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len() == self.capacity()
+    }
+}
+
+/// The reading end of an SPSC ring buffer.
+pub struct Consumer<T> {
+    inner: Arc<Inner<T>>,
+    head: usize,
+    cached_tail: usize,
+}
+
+// Only the consumer reads slots between `head` and `tail`, and there is exactly one
+// consumer, so it can move to another thread as long as the values can.
+unsafe impl<T: Send> Send for Consumer<T> {}
+
+impl<T> Consumer<T> {
+    /// Number of filled slots, reloading the producer's index only if the cached one
+    /// says there are fewer than `wanted`.
+    fn filled_slots(&mut self, wanted: usize) -> usize {
+        let mut filled = self.inner.distance(self.head, self.cached_tail);
+        if filled < wanted {
+            self.cached_tail = self.inner.tail.load(Ordering::Acquire);
+            filled = self.inner.distance(self.head, self.cached_tail);
+        }
+        filled
+    }
+
+    /// Pops the oldest value, or returns `None` if the buffer is empty.
+    pub fn pop(&mut self) -> Option<T> {
+        if self.filled_slots(1) == 0 {
+            return None;
+        }
+        let value = unsafe { (*self.inner.slot(self.head)).assume_init_read() };
+        self.head = self.inner.next(self.head);
+        self.inner.head.store(self.head, Ordering::Release);
+        Some(value)
+    }
+
+    /// Fills `out` with as many values as are available and returns how many were
+    /// popped. The slots are handed back to the producer all at once.
+    pub fn pop_into(&mut self, out: &mut [T]) -> usize
+    where
+        T: Copy,
+    {
+        let count = self.filled_slots(out.len()).min(out.len());
+        for value in &mut out[..count] {
+            *value = unsafe { (*self.inner.slot(self.head)).assume_init_read() };
+            self.head = self.inner.next(self.head);
+        }
+        self.inner.head.store(self.head, Ordering::Release);
+        count
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+
+    /// The number of values in the buffer, the producer may have added some since.
+    pub fn len(&self) -> usize {
+        let tail = self.inner.tail.load(Ordering::Acquire);
+        self.inner.distance(self.head, tail)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_full_and_empty() {
+        let (mut producer, mut consumer) = channel(2);
+        assert_eq!(consumer.pop(), None);
+        assert_eq!(producer.push(1), Ok(()));
+        assert_eq!(producer.push(2), Ok(()));
+        assert!(producer.is_full());
+        assert_eq!(producer.push(3), Err(3));
+        assert_eq!(consumer.pop(), Some(1));
+        assert_eq!(producer.push(3), Ok(()));
+        assert_eq!(consumer.pop(), Some(2));
+        assert_eq!(consumer.pop(), Some(3));
+        assert!(consumer.is_empty());
+    }
+
+    #[test]
+    fn test_slices_wrap_around() {
+        let (mut producer, mut consumer) = channel(5);
+        let mut out = [0; 4];
+        for round in 0..20 {
+            let values = [round, round + 1, round + 2, round + 3];
+            assert_eq!(producer.push_slice(&values), 4);
+            assert_eq!(producer.push_slice(&values), 1);
+            assert_eq!(consumer.pop_into(&mut out), 4);
+            assert_eq!(out, values);
+            assert_eq!(consumer.pop(), Some(round));
+        }
+    }
+
+    #[test]
+    fn test_transfer_between_threads() {
+        let (mut producer, mut consumer) = channel(64);
+        let handle = thread::spawn(move || {
+            for i in 0..100000 {
+                let mut value = i;
+                while let Err(rejected) = producer.push(value) {
+                    value = rejected;
+                    thread::yield_now();
+                }
+            }
+        });
+        let mut expected = 0;
+        while expected < 100000 {
+            match consumer.pop() {
+                Some(value) => {
+                    assert_eq!(value, expected);
+                    expected += 1;
+                }
+                None => thread::yield_now(),
+            }
+        }
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_drop_remaining_values() {
+        let value = Arc::new(());
+        let (mut producer, mut consumer) = channel(4);
+        for _ in 0..3 {
+            producer.push(value.clone()).unwrap();
+        }
+        consumer.pop().unwrap();
+        drop(producer);
+        assert_eq!(Arc::strong_count(&value), 3);
+        drop(consumer);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+}
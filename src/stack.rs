@@ -0,0 +1,1629 @@
+//! The Treiber stack and the iterator [`LockFreeStack::take_all`] drains it into.
+
+use crate::{
+    allocator::{Global, NodeAllocator},
+    backoff::{Backoff, ExponentialSpin},
+    cache_padded::CachePadded,
+    hazard::HazardPointers,
+    reclaim::{Guard, Reclaim},
+    sync::order::{ACQUIRE, RELAXED, RELEASE},
+    sync::{AtomicPtr, AtomicUsize, Ordering},
+    tagged::AtomicTaggedPtr,
+    waiters::{Registration, Unparker, Waiters},
+};
+use alloc::alloc::handle_alloc_error;
+use core::{
+    alloc::Layout,
+    fmt,
+    future::Future,
+    marker::PhantomData,
+    mem::ManuallyDrop,
+    ops::Deref,
+    pin::Pin,
+    ptr::{self, NonNull},
+    task::{Context, Poll},
+};
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
+
+pub(crate) struct Node<T> {
+    data: T,
+    // atomic because `take_all_fifo` relinks nodes that a stale pop may still read
+    next: AtomicPtr<Node<T>>,
+    // belongs to the stack's pool, see `NodePool`
+    pooled: bool,
+}
+
+/// Allocates and frees the nodes of a stack, and keeps the nodes whose elements
+/// have been taken out for later pushes.
+///
+/// The free nodes form a Treiber stack of their own. A push taking the top node
+/// protects it through the stack's domain before it reads the link. Nodes only
+/// come back to the pool through the domain's `free` callback, so the protected
+/// node cannot be taken, handed back and put on top again before the push's CAS,
+/// which rules out ABA without relying on the tag.
+struct NodePool<T, A: NodeAllocator> {
+    free: AtomicTaggedPtr<Node<T>>,
+    // pooled nodes, whether they are free or in use
+    owned: AtomicUsize,
+    // zero turns the pool off
+    capacity: usize,
+    allocator: A,
+}
+
+impl<T, A: NodeAllocator> NodePool<T, A> {
+    fn new(allocator: A) -> Self {
+        Self {
+            free: AtomicTaggedPtr::new(ptr::null_mut()),
+            owned: AtomicUsize::new(0),
+            capacity: 0,
+            allocator,
+        }
+    }
+
+    /// Returns a node holding `data`, a free one if there is any.
+    fn alloc<R: Reclaim>(&self, reclaim: &R, data: T) -> *mut Node<T> {
+        match self.try_alloc(reclaim, data) {
+            Ok(node) => node,
+            Err(_) => handle_alloc_error(Layout::new::<Node<T>>()),
+        }
+    }
+
+    /// Like `alloc`, but hands `data` back if the allocator is exhausted.
+    fn try_alloc<R: Reclaim>(&self, reclaim: &R, data: T) -> Result<*mut Node<T>, T> {
+        if let Some(node) = self.take(reclaim) {
+            unsafe {
+                ptr::addr_of_mut!((*node).data).write(data);
+                (*node).next.store(ptr::null_mut(), RELAXED);
+            }
+            return Ok(node);
+        }
+        let Some(block) = self.allocator.allocate(Layout::new::<Node<T>>()) else {
+            return Err(data);
+        };
+        let node = block.cast::<Node<T>>().as_ptr();
+        unsafe {
+            node.write(Node {
+                data,
+                next: AtomicPtr::new(ptr::null_mut()),
+                pooled: false,
+            })
+        };
+        Ok(node)
+    }
+
+    /// Gives the memory of a node back to the allocator, without dropping its data.
+    fn deallocate(&self, node: *mut u8) {
+        let node = unsafe { NonNull::new_unchecked(node) };
+        unsafe { self.allocator.deallocate(node, Layout::new::<Node<T>>()) };
+    }
+
+    /// Takes a free node, if there is one.
+    fn take<R: Reclaim>(&self, reclaim: &R) -> Option<*mut Node<T>> {
+        // the pool is empty whenever it is off, so that case skips the guard
+        if self.free.load(RELAXED).is_null() {
+            return None;
+        }
+        let mut guard = reclaim.guard();
+        loop {
+            // slot 1, a pop holding slot 0 uses a guard of its own anyway. The
+            // protecting load acquires what `recycle` released, so our writes to
+            // the node come after the previous owner is done with it
+            let top = guard.protect(1, &self.free);
+            if top.is_null() {
+                return None;
+            }
+            let next = unsafe { (*top.ptr()).next.load(RELAXED) };
+            if self
+                .free
+                .compare_exchange_weak(top, next, ACQUIRE, RELAXED)
+                .is_ok()
+            {
+                return Some(top.ptr());
+            }
+        }
+    }
+
+    /// Takes back a node whose data has been moved out and that no other thread
+    /// can read anymore, which only the domain's `free` callback knows. A node that is not pooled yet joins the pool if it has
+    /// room and is freed otherwise.
+    fn recycle(&self, node: *mut u8) {
+        let node = node.cast::<Node<T>>();
+        if !unsafe { (*node).pooled } {
+            let joined = self.owned.fetch_update(RELAXED, RELAXED, |owned| {
+                (owned < self.capacity).then_some(owned + 1)
+            });
+            if joined.is_err() {
+                self.deallocate(node.cast());
+                return;
+            }
+            unsafe { (*node).pooled = true };
+        }
+        let mut top = self.free.load(RELAXED);
+        loop {
+            unsafe { (*node).next.store(top.ptr(), RELAXED) };
+            match self.free.compare_exchange_weak(top, node, RELEASE, RELAXED) {
+                Ok(_) => return,
+                Err(current) => top = current,
+            }
+        }
+    }
+
+    /// Lets go of a node in use, which then gets freed instead of recycled. Only
+    /// safe while no push can be taking nodes, or one could still read its link.
+    fn release(&mut self, node: *mut Node<T>) {
+        if unsafe { (*node).pooled } {
+            unsafe { (*node).pooled = false };
+            self.owned.fetch_sub(1, RELAXED);
+        }
+    }
+
+    /// Frees free nodes until the pool owns at most `capacity` nodes.
+    fn shrink(&mut self) {
+        while self.owned.load(RELAXED) > self.capacity {
+            let top = self.free.load(RELAXED).ptr();
+            if top.is_null() {
+                return;
+            }
+            let next = unsafe { (*top).next.load(RELAXED) };
+            self.free.swap(next, RELAXED);
+            self.owned.fetch_sub(1, RELAXED);
+            self.deallocate(top.cast());
+        }
+    }
+}
+
+impl<T, A: NodeAllocator> Drop for NodePool<T, A> {
+    fn drop(&mut self) {
+        // the nodes in use are freed by whoever holds them
+        let mut current = self.free.load(RELAXED).ptr();
+        while !current.is_null() {
+            let next = unsafe { (*current).next.load(RELAXED) };
+            self.deallocate(current.cast());
+            current = next;
+        }
+    }
+}
+
+/// A lock-free Treiber stack.
+///
+/// Popped nodes are handed to the reclamation scheme `R` instead of being freed
+/// right away, so other threads can keep reading them until they move on. Once
+/// they are safe to free they can be kept for later pushes instead, see
+/// [`set_pool_capacity`](Self::set_pool_capacity). A push or pop that loses the
+/// race for `head` waits according to `B` before retrying. Nodes are allocated
+/// through `A`.
+///
+/// The [`Debug`](fmt::Debug) output only shows [`approx_len`](Self::approx_len).
+/// Formatting takes `&self`, so a concurrent pop could move an element out and
+/// drop it while `fmt` is reading it. [`iter`](Self::iter) lists the elements
+/// from a snapshot taken through `&mut self`.
+///
+/// ```
+/// use lock_free::LockFreeStack;
+///
+/// let stack = LockFreeStack::new();
+/// std::thread::scope(|s| {
+///     s.spawn(|| stack.push(1));
+///     s.spawn(|| stack.push(2));
+/// });
+/// assert!(stack.pop().is_some());
+/// ```
+pub struct LockFreeStack<
+    T,
+    R: Reclaim = HazardPointers,
+    B: Backoff = ExponentialSpin,
+    A: NodeAllocator = Global,
+> {
+    // versioned so a CAS cannot succeed against a recycled node at the same address
+    head: AtomicTaggedPtr<Node<T>>,
+    reclaim: R,
+    // cloned by every push and pop to pace their retries
+    backoff: B,
+    // raised before an element is linked in and lowered after it is unlinked, so it
+    // never drops below the number of elements; padded to keep it off head's line
+    len: CachePadded<AtomicUsize>,
+    // threads parked in `pop_blocking` until a push
+    waiters: Waiters,
+    pool: NodePool<T, A>,
+}
+
+// `head` only carries a tagged address, which would make the stack `Send` and
+// `Sync` whatever `T` is. Sending it sends the elements still linked below `head`.
+unsafe impl<T: Send, R: Reclaim, B: Backoff, A: NodeAllocator> Send for LockFreeStack<T, R, B, A> {}
+
+// An element moves in with the push whose CAS links its node and out with the one
+// pop whose CAS unlinks it, or with the swap in `take_all`. Pops that lost the race
+// for the node only read its `next`. So like `Mutex<T>` the stack only needs
+// `T: Send` to be shared. The one `&T` it hands out through `&self` comes from
+// `peek`, which requires `T: Sync` itself; `iter` needs `&mut self`.
+unsafe impl<T: Send, R: Reclaim, B: Backoff, A: NodeAllocator> Sync for LockFreeStack<T, R, B, A> {}
+
+impl<T> LockFreeStack<T> {
+    /// Creates an empty stack that reclaims nodes with hazard pointers.
+    pub fn new() -> Self {
+        Self::with_reclaim(HazardPointers::new())
+    }
+}
+
+impl<T, R, B, A> Default for LockFreeStack<T, R, B, A>
+where
+    R: Reclaim + Default,
+    B: Backoff + Default,
+    A: NodeAllocator + Default,
+{
+    fn default() -> Self {
+        Self::with_reclaim_backoff_and_allocator(R::default(), B::default(), A::default())
+    }
+}
+
+impl<T, B: Backoff> LockFreeStack<T, HazardPointers, B> {
+    /// Creates an empty stack that waits between failed CAS attempts with `backoff`.
+    pub fn with_backoff(backoff: B) -> Self {
+        Self::with_reclaim_and_backoff(HazardPointers::new(), backoff)
+    }
+}
+
+impl<T, R: Reclaim> LockFreeStack<T, R> {
+    /// Creates an empty stack that reclaims nodes through `reclaim`.
+    pub fn with_reclaim(reclaim: R) -> Self {
+        Self::with_reclaim_and_backoff(reclaim, ExponentialSpin::default())
+    }
+}
+
+impl<T, A: NodeAllocator> LockFreeStack<T, HazardPointers, ExponentialSpin, A> {
+    /// Creates an empty stack that allocates its nodes through `allocator`.
+    pub fn with_allocator(allocator: A) -> Self {
+        Self::with_reclaim_backoff_and_allocator(
+            HazardPointers::new(),
+            ExponentialSpin::default(),
+            allocator,
+        )
+    }
+}
+
+impl<T, R: Reclaim, B: Backoff> LockFreeStack<T, R, B> {
+    /// Creates an empty stack that reclaims nodes through `reclaim` and waits between
+    /// failed CAS attempts with `backoff`.
+    pub fn with_reclaim_and_backoff(reclaim: R, backoff: B) -> Self {
+        Self::with_reclaim_backoff_and_allocator(reclaim, backoff, Global)
+    }
+}
+
+impl<T, R: Reclaim, B: Backoff, A: NodeAllocator> LockFreeStack<T, R, B, A> {
+    /// Creates an empty stack that reclaims nodes through `reclaim`, waits between
+    /// failed CAS attempts with `backoff` and allocates nodes through `allocator`.
+    pub fn with_reclaim_backoff_and_allocator(reclaim: R, backoff: B, allocator: A) -> Self {
+        Self {
+            head: AtomicTaggedPtr::new(ptr::null_mut()),
+            reclaim,
+            backoff,
+            len: CachePadded::new(AtomicUsize::new(0)),
+            waiters: Waiters::new(),
+            pool: NodePool::new(allocator),
+        }
+    }
+
+    /// The allocator the nodes come from.
+    pub fn allocator(&self) -> &A {
+        &self.pool.allocator
+    }
+
+    /// Keeps up to `capacity` nodes around for pushes to reuse once their elements
+    /// have been popped, so that a stack which keeps filling up and draining again
+    /// stops going to the allocator. Pooled nodes are only freed here or when the
+    /// stack is dropped. The pool is off, with a capacity of zero, until this is
+    /// called.
+    ///
+    /// ```
+    /// use lock_free::LockFreeStack;
+    ///
+    /// let mut stack = LockFreeStack::new();
+    /// stack.set_pool_capacity(1024);
+    /// stack.push(1);
+    /// assert_eq!(stack.pop(), Some(1));
+    /// ```
+    pub fn set_pool_capacity(&mut self, capacity: usize) {
+        self.pool.capacity = capacity;
+        // nobody can take nodes from the pool now, so the nodes in use can leave it
+        // and the pool can shrink down to the free ones
+        let pool = &self.pool;
+        self.reclaim.reclaim_all(|node| pool.recycle(node));
+        let mut current = self.head.load(RELAXED).ptr();
+        while !current.is_null() {
+            self.pool.release(current);
+            current = unsafe { (*current).next.load(RELAXED) };
+        }
+        self.pool.shrink();
+    }
+
+    /// Allocates a node for `data`, reusing a pooled one if there is any.
+    pub(crate) fn alloc_node(&self, data: T) -> *mut Node<T> {
+        self.pool.alloc(&self.reclaim, data)
+    }
+
+    /// Takes the data out of a node that was never shared through `head` and
+    /// retires the node. It may have been pooled, and a push taking nodes from the
+    /// pool may still have it protected.
+    ///
+    /// # Safety
+    /// `node` must come from [`alloc_node`](Self::alloc_node) and no other thread
+    /// may hold it.
+    pub(crate) unsafe fn take_data(&self, guard: &mut R::Guard<'_>, node: *mut Node<T>) -> T {
+        let data = unsafe { ptr::read(&(*node).data) };
+        unsafe { guard.retire(node.cast(), |node| self.pool.recycle(node)) };
+        data
+    }
+
+    pub fn push(&self, data: T) {
+        self.link(self.alloc_node(data));
+    }
+
+    /// Like [`push`](Self::push), but hands `data` back instead of aborting if the
+    /// allocator has no memory left for its node.
+    ///
+    /// ```
+    /// use lock_free::LockFreeStack;
+    ///
+    /// let stack = LockFreeStack::new();
+    /// assert_eq!(stack.try_push(1), Ok(()));
+    /// assert_eq!(stack.pop(), Some(1));
+    /// ```
+    pub fn try_push(&self, data: T) -> Result<(), T> {
+        self.link(self.pool.try_alloc(&self.reclaim, data)?);
+        Ok(())
+    }
+
+    fn link(&self, new_node_ptr: *mut Node<T>) {
+        self.len.fetch_add(1, RELAXED);
+        let mut backoff = self.backoff.clone();
+        while !self.try_link(new_node_ptr) {
+            // a thread has disturbed the operation between load and exchange, let it
+            // finish before we retry
+            backoff.backoff();
+        }
+        self.waiters.notify(1);
+    }
+
+    /// Pushes every item of `items` with a single successful CAS. The items end up
+    /// as if they had been pushed one by one in iteration order, so the last one is
+    /// on top, but no other push or pop can land between them.
+    ///
+    /// ```
+    /// use lock_free::LockFreeStack;
+    ///
+    /// let stack = LockFreeStack::new();
+    /// stack.push_many([1, 2, 3]);
+    /// assert_eq!(stack.pop(), Some(3));
+    /// ```
+    pub fn push_many(&self, items: impl IntoIterator<Item = T>) {
+        let mut items = items.into_iter().map(|data| self.alloc_node(data));
+        if let Some(bottom) = items.next() {
+            self.link_chain(bottom, items);
+        }
+    }
+
+    /// Like [`push_many`](Self::push_many), but stops at the first item the
+    /// allocator has no memory left for. The items before it are still pushed
+    /// together and that item is handed back. Pass `iter.by_ref()` to keep the
+    /// items after it as well.
+    ///
+    /// ```
+    /// use lock_free::LockFreeStack;
+    ///
+    /// let stack = LockFreeStack::new();
+    /// assert_eq!(stack.try_push_many([1, 2, 3]), Ok(()));
+    /// assert_eq!(stack.pop(), Some(3));
+    /// ```
+    pub fn try_push_many(&self, items: impl IntoIterator<Item = T>) -> Result<(), T> {
+        let mut rejected = None;
+        let mut items = items.into_iter().map_while(|data| {
+            let node = self.pool.try_alloc(&self.reclaim, data);
+            node.map_err(|data| rejected = Some(data)).ok()
+        });
+        if let Some(bottom) = items.next() {
+            self.link_chain(bottom, items);
+        }
+        rejected.map_or(Ok(()), Err)
+    }
+
+    /// Links `bottom` and the nodes after it up locally, each on top of the one
+    /// before, and pushes the chain.
+    fn link_chain(&self, bottom: *mut Node<T>, nodes: impl Iterator<Item = *mut Node<T>>) {
+        let mut top = bottom;
+        let mut count = 1;
+        for node in nodes {
+            unsafe { (*node).next.store(top, RELAXED) };
+            top = node;
+            count += 1;
+        }
+        self.len.fetch_add(count, RELAXED);
+
+        let mut backoff = self.backoff.clone();
+        while !self.try_splice(top, bottom) {
+            backoff.backoff();
+        }
+        self.waiters.notify(count);
+    }
+
+    /// Makes a single attempt at linking `new_node_ptr` in as the new head.
+    pub(crate) fn try_link(&self, new_node_ptr: *mut Node<T>) -> bool {
+        self.try_splice(new_node_ptr, new_node_ptr)
+    }
+
+    /// Makes a single attempt at linking the chain from `top` down to `bottom` in
+    /// on top of the current head.
+    fn try_splice(&self, top: *mut Node<T>, bottom: *mut Node<T>) -> bool {
+        // atomicly get a pointer to node pointed by head, we never read through it
+        // so it needs no ordering
+        let current = self.head.load(RELAXED);
+        // set the bottom node's next to the node pointed by head currently
+        unsafe {
+            (*bottom).next.store(current.ptr(), RELAXED);
+        }
+        // If current and head are still pointing to the same node then exchange head with the pointer pointing to the new top.
+        // Release publishes the contents of the whole chain to the pops that acquire it,
+        // SeqCst orders the push before the waiter count `Waiters::notify` loads
+        self.head
+            .compare_exchange_weak(current, top, Ordering::SeqCst, RELAXED)
+            .is_ok()
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        let mut guard = self.guard();
+        let mut backoff = self.backoff.clone();
+        loop {
+            if let Ok(data) = self.try_pop(&mut guard) {
+                if data.is_some() {
+                    self.len.fetch_sub(1, RELAXED);
+                }
+                return data;
+            }
+            backoff.backoff();
+        }
+    }
+
+    /// Pops the top element, parking the thread until a push if the stack is empty.
+    #[cfg(feature = "std")]
+    pub fn pop_blocking(&self) -> T {
+        loop {
+            if let Some(data) = self.pop_until(None) {
+                return data;
+            }
+        }
+    }
+
+    /// Like [`pop_blocking`](Self::pop_blocking), but gives up and returns `None`
+    /// once `timeout` has passed.
+    #[cfg(feature = "std")]
+    pub fn pop_timeout(&self, timeout: Duration) -> Option<T> {
+        // a deadline too far out to represent is as good as none
+        self.pop_until(Instant::now().checked_add(timeout))
+    }
+
+    /// Returns a future that pops the top element, waiting for a push if the stack
+    /// is empty. Dropping the future before it completes loses no element.
+    pub fn pop_async(&self) -> PopFuture<'_, T, R, B, A> {
+        PopFuture {
+            stack: self,
+            waiter: None,
+        }
+    }
+
+    #[cfg(feature = "std")]
+    fn pop_until(&self, deadline: Option<Instant>) -> Option<T> {
+        self.waiters.wait_until(deadline, || self.pop())
+    }
+
+    /// Makes a single attempt at unlinking the head, `Err` means another thread won
+    /// the race for it.
+    pub(crate) fn try_pop(&self, guard: &mut R::Guard<'_>) -> Result<Option<T>, ()> {
+        // protect the head before touching it so no other thread can free it under us
+        let current_head = guard.protect(0, &self.head);
+        if current_head.is_null() {
+            return Ok(None);
+        }
+        // only read the link, copying the whole node would duplicate `data`
+        let next = unsafe { (*current_head.ptr()).next.load(RELAXED) };
+
+        // If head has not changed since load, point head to next node. The protecting
+        // load already acquired the node's contents, and every later pop acquires
+        // `next` through the release sequence of the push that linked it, since all
+        // writes to head are read-modify-writes. Ordering the unlink before the
+        // domain looks for readers is up to `retire`, which fences first
+        self.head
+            .compare_exchange_weak(current_head, next, RELAXED, RELAXED)
+            .map_err(|_| ())?;
+        // Now we own the data, but other threads may still be reading the node so
+        // it is retired instead of deallocated or pooled
+        let data = unsafe { ptr::read(&(*current_head.ptr()).data) };
+        unsafe { guard.retire(current_head.ptr().cast(), |node| self.pool.recycle(node)) };
+        Ok(Some(data))
+    }
+
+    /// Borrows the top element without popping it. The returned guard keeps the
+    /// element's node from being freed, but not the element from being popped: a
+    /// pop moves the value out of the node while the guard may still read it.
+    /// That copy is only harmless for `T: Copy`, whose values own nothing a pop
+    /// could free.
+    ///
+    /// ```
+    /// use lock_free::LockFreeStack;
+    ///
+    /// let stack = LockFreeStack::new();
+    /// stack.push(1);
+    /// assert_eq!(stack.peek().as_deref(), Some(&1));
+    /// ```
+    pub fn peek(&self) -> Option<Peek<'_, T, R>>
+    where
+        T: Copy + Sync,
+    {
+        let mut guard = self.guard();
+        let head = guard.protect(0, &self.head);
+        if head.is_null() {
+            return None;
+        }
+        Some(Peek {
+            data: unsafe { ptr::addr_of!((*head.ptr()).data) },
+            _guard: guard,
+        })
+    }
+
+    /// Detaches every element with a single swap of `head` and returns them from
+    /// the top down.
+    ///
+    /// A pop that lost the race may still be reading the detached nodes, so the
+    /// iterator borrows the stack to retire each node into its domain once the
+    /// element has been taken out.
+    pub fn take_all(&self) -> IntoIter<'_, T, R, A> {
+        // acquire the contents of every node, like a pop does for one
+        let head = self.head.swap(ptr::null_mut(), ACQUIRE);
+        let mut count = 0;
+        let mut current = head.ptr();
+        while !current.is_null() {
+            count += 1;
+            current = unsafe { (*current).next.load(RELAXED) };
+        }
+        self.len.fetch_sub(count, RELAXED);
+        IntoIter {
+            domain: Domain::Shared(&self.reclaim, &self.pool, PhantomData),
+            next: head.ptr(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Like [`take_all`](Self::take_all), but returns the elements in the order
+    /// they were pushed.
+    pub fn take_all_fifo(&self) -> IntoIter<'_, T, R, A> {
+        let mut iter = self.take_all();
+        iter.reverse();
+        iter
+    }
+
+    pub(crate) fn guard(&self) -> R::Guard<'_> {
+        self.reclaim.guard()
+    }
+
+    /// Returns `true` if the stack had no elements at the moment it was checked.
+    /// It only loads `head`, so it is safe to call at any time.
+    pub fn is_empty(&self) -> bool {
+        self.head.load(RELAXED).is_null()
+    }
+
+    /// The number of elements, from a counter that pushes raise before their
+    /// elements show up and pops lower after theirs are gone. While other threads
+    /// are pushing and popping it can be off by the number of operations in flight.
+    pub fn approx_len(&self) -> usize {
+        self.len.load(RELAXED)
+    }
+
+    /// Borrows the elements from the top down. It needs `&mut self` for the same
+    /// reason as [`len`](Self::len).
+    ///
+    /// ```
+    /// use lock_free::LockFreeStack;
+    ///
+    /// let mut stack: LockFreeStack<_> = (1..4).collect();
+    /// assert!(stack.iter().eq(&[3, 2, 1]));
+    /// ```
+    pub fn iter(&mut self) -> impl Iterator<Item = &T> + '_ {
+        let mut current = self.head.load(RELAXED).ptr();
+        core::iter::from_fn(move || {
+            if current.is_null() {
+                return None;
+            }
+            let node = unsafe { &*current };
+            current = node.next.load(RELAXED);
+            Some(&node.data)
+        })
+    }
+
+    /// The exact number of elements, counted by walking the stack. It needs
+    /// `&mut self` because nodes could be freed under the walk otherwise.
+    pub fn len(&mut self) -> usize {
+        let mut current = self.head.load(RELAXED).ptr();
+        let mut count = 0;
+        while !current.is_null() {
+            count += 1;
+            current = unsafe { (*current).next.load(RELAXED) };
+        }
+        count
+    }
+}
+
+impl<T, R, B, A> FromIterator<T> for LockFreeStack<T, R, B, A>
+where
+    R: Reclaim + Default,
+    B: Backoff + Default,
+    A: NodeAllocator + Default,
+{
+    /// Pushes the items in iteration order, so the last one ends up on top.
+    fn from_iter<I: IntoIterator<Item = T>>(items: I) -> Self {
+        let stack = Self::default();
+        stack.push_many(items);
+        stack
+    }
+}
+
+impl<T, R: Reclaim, B: Backoff, A: NodeAllocator> Extend<T> for LockFreeStack<T, R, B, A> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, items: I) {
+        self.push_many(items);
+    }
+}
+
+impl<T, R: Reclaim, B: Backoff, A: NodeAllocator> Extend<T> for &LockFreeStack<T, R, B, A> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, items: I) {
+        self.push_many(items);
+    }
+}
+
+impl<T, R: Reclaim, B: Backoff, A: NodeAllocator> IntoIterator for LockFreeStack<T, R, B, A> {
+    type Item = T;
+    type IntoIter = IntoIter<'static, T, R, A>;
+
+    /// Returns the elements from the top down.
+    fn into_iter(self) -> IntoIter<'static, T, R, A> {
+        // the iterator takes over the chain, the domain and the pool, the fields
+        // that own anything else are dropped here
+        let mut stack = ManuallyDrop::new(self);
+        unsafe {
+            ptr::drop_in_place(&mut stack.backoff);
+            ptr::drop_in_place(&mut stack.waiters);
+        }
+        let (reclaim, pool) = unsafe { (ptr::read(&stack.reclaim), ptr::read(&stack.pool)) };
+        IntoIter {
+            domain: Domain::Owned(reclaim, pool),
+            next: stack.head.load(RELAXED).ptr(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, R: Reclaim, B: Backoff, A: NodeAllocator> fmt::Debug for LockFreeStack<T, R, B, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LockFreeStack")
+            .field("approx_len", &self.approx_len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T, R: Reclaim, B: Backoff, A: NodeAllocator> Drop for LockFreeStack<T, R, B, A> {
+    fn drop(&mut self) {
+        // nobody else can reach the stack anymore, so the nodes can be freed directly
+        let mut current = self.head.load(RELAXED).ptr();
+        while !current.is_null() {
+            let next = unsafe { (*current).next.load(RELAXED) };
+            unsafe { ptr::drop_in_place(ptr::addr_of_mut!((*current).data)) };
+            self.pool.deallocate(current.cast());
+            current = next;
+        }
+        // popped nodes still waiting in the domain only need their memory freed
+        let pool = &self.pool;
+        self.reclaim.reclaim_all(|node| pool.deallocate(node));
+    }
+}
+
+/// The future returned by [`LockFreeStack::pop_async`].
+pub struct PopFuture<
+    'a,
+    T,
+    R: Reclaim = HazardPointers,
+    B: Backoff = ExponentialSpin,
+    A: NodeAllocator = Global,
+> {
+    stack: &'a LockFreeStack<T, R, B, A>,
+    waiter: Option<Registration<'a>>,
+}
+
+impl<T, R: Reclaim, B: Backoff, A: NodeAllocator> Future for PopFuture<'_, T, R, B, A> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let this = self.get_mut();
+        // being polled again means any notification has been acted on, so the old
+        // registration is released without passing it on
+        this.waiter = None;
+        if let Some(data) = this.stack.pop() {
+            return Poll::Ready(data);
+        }
+        let waiter = this
+            .stack
+            .waiters
+            .register(Unparker::Waker(cx.waker().clone()));
+        // a push between the pop above and the registration did not see us
+        if let Some(data) = this.stack.pop() {
+            waiter.cancel();
+            return Poll::Ready(data);
+        }
+        this.waiter = Some(waiter);
+        Poll::Pending
+    }
+}
+
+impl<T, R: Reclaim, B: Backoff, A: NodeAllocator> Drop for PopFuture<'_, T, R, B, A> {
+    fn drop(&mut self) {
+        // a push may have woken us for an element we will never take, wake someone
+        // else for it
+        if let Some(waiter) = self.waiter.take() {
+            waiter.cancel();
+        }
+    }
+}
+
+/// The top element of a stack, borrowed by [`LockFreeStack::peek`].
+pub struct Peek<'a, T, R: Reclaim + 'a = HazardPointers> {
+    data: *const T,
+    // protects the node `data` points into
+    _guard: R::Guard<'a>,
+}
+
+impl<T, R: Reclaim> Deref for Peek<'_, T, R> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.data }
+    }
+}
+
+/// The elements detached by [`LockFreeStack::take_all`], or those of a consumed
+/// stack. Dropping it drops the elements it has not yielded.
+pub struct IntoIter<'a, T, R: Reclaim = HazardPointers, A: NodeAllocator = Global> {
+    domain: Domain<'a, T, R, A>,
+    next: *mut Node<T>,
+    // the iterator owns the elements left in the chain
+    _marker: PhantomData<T>,
+}
+
+// The pointers are what keeps the iterator from being `Send` on its own. It owns
+// the elements left in the chain, so it needs `T: Send` like `Vec`'s iterator.
+// Through a `Shared` domain it only retires nodes, which the borrowed stack
+// already lets any thread do for `T: Send`.
+unsafe impl<T: Send, R: Reclaim, A: NodeAllocator> Send for IntoIter<'_, T, R, A> {}
+
+/// Where an [`IntoIter`] puts the nodes it has taken the elements out of.
+enum Domain<'a, T, R, A: NodeAllocator> {
+    /// The stack is still shared and stale pops may read the nodes, which go to
+    /// its pool once they are safe to free. The domain and the pool are borrowed
+    /// from the stack for `'a`, they are only pointers so that an
+    /// `IntoIter<'static>` needs neither `R: 'static` nor `T: 'static`.
+    Shared(*const R, *const NodePool<T, A>, PhantomData<&'a ()>),
+    /// The stack was consumed, only the nodes it had already retired are left.
+    /// Every node goes straight back to the allocator.
+    Owned(R, NodePool<T, A>),
+}
+
+impl<T, R: Reclaim, A: NodeAllocator> IntoIter<'_, T, R, A> {
+    /// Reverses the chain in place. The links are atomics because stale pops may
+    /// read them at the same time, but their CAS on `head` fails whatever they read.
+    fn reverse(&mut self) {
+        let mut previous = ptr::null_mut();
+        let mut current = self.next;
+        while !current.is_null() {
+            let next = unsafe { (*current).next.load(RELAXED) };
+            unsafe { (*current).next.store(previous, RELAXED) };
+            previous = current;
+            current = next;
+        }
+        self.next = previous;
+    }
+}
+
+impl<T, R: Reclaim, A: NodeAllocator> Iterator for IntoIter<'_, T, R, A> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.next.is_null() {
+            return None;
+        }
+        let node = self.next;
+        self.next = unsafe { (*node).next.load(RELAXED) };
+        let data = unsafe { ptr::read(&(*node).data) };
+        match &self.domain {
+            Domain::Shared(reclaim, pool, _) => unsafe {
+                (**reclaim)
+                    .guard()
+                    .retire(node.cast(), |node| (**pool).recycle(node))
+            },
+            Domain::Owned(_, pool) => pool.deallocate(node.cast()),
+        }
+        Some(data)
+    }
+}
+
+impl<T, R: Reclaim, A: NodeAllocator> Drop for IntoIter<'_, T, R, A> {
+    fn drop(&mut self) {
+        self.for_each(drop);
+        if let Domain::Owned(reclaim, pool) = &mut self.domain {
+            reclaim.reclaim_all(|node| pool.deallocate(node));
+        }
+    }
+}
+
+#[cfg(all(test, not(feature = "loom")))]
+mod tests {
+    use super::*;
+    use crate::{
+        backoff::{Jitter, NoBackoff},
+        epoch::Epoch,
+        linearizability::{linearize, Recorder, SequentialStack, StackOp},
+    };
+    use std::{
+        pin::pin,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        task::{Wake, Waker},
+        thread,
+        time::Duration,
+    };
+
+    /// Counts how many times it has been woken
+    #[derive(Default)]
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Unparks the thread that blocks on a future, enough of an executor for tests
+    struct ThreadWaker(thread::Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            thread::park();
+        }
+    }
+
+    /// Keeps track of the nodes it has handed out and not got back yet
+    #[derive(Default)]
+    struct CountingAllocator(AtomicUsize);
+
+    unsafe impl NodeAllocator for CountingAllocator {
+        fn allocate(&self, layout: Layout) -> Option<NonNull<u8>> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Global.allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            self.0.fetch_sub(1, Ordering::SeqCst);
+            unsafe { Global.deallocate(ptr, layout) }
+        }
+    }
+
+    /// Counts how many times values of it have been dropped
+    struct DropCounter(Arc<AtomicUsize>);
+
+    impl Drop for DropCounter {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_send_sync() {
+        fn assert_send_sync<S: Send + Sync>() {}
+        assert_send_sync::<LockFreeStack<Box<u8>>>();
+        assert_send_sync::<LockFreeStack<std::cell::Cell<u8>, Epoch>>();
+    }
+
+    #[test]
+    fn test_push() {
+        let mut stack = LockFreeStack::new();
+        thread::scope(|s| {
+            for i in 0..10 {
+                let stack = &stack;
+                s.spawn(move || {
+                    for _ in 0..100000 {
+                        stack.push(i);
+                    }
+                });
+            }
+        });
+        assert_eq!(stack.len(), 1_000_000)
+    }
+
+    #[test]
+    fn test_pop() {
+        let mut stack = LockFreeStack::new();
+        for i in 0..100000 {
+            stack.push(i);
+        }
+        thread::scope(|s| {
+            for _ in 0..10 {
+                s.spawn(|| {
+                    for _ in 0..100000 {
+                        let _ = stack.pop();
+                    }
+                });
+            }
+        });
+        assert_eq!(stack.len(), 0)
+    }
+
+    #[test]
+    fn test_push_pop_contention() {
+        let mut stack = LockFreeStack::new();
+        let popped: usize = thread::scope(|s| {
+            let handles: Vec<_> = (0..8)
+                .map(|i| {
+                    let stack = &stack;
+                    s.spawn(move || {
+                        let mut popped = 0;
+                        for j in 0..50000 {
+                            stack.push(i * 50000 + j);
+                            if stack.pop().is_some() {
+                                popped += 1;
+                            }
+                        }
+                        popped
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).sum()
+        });
+        assert_eq!(popped + stack.len(), 400_000)
+    }
+
+    #[test]
+    fn test_backoff_strategies() {
+        fn contend<B: Backoff>(backoff: B) {
+            let mut stack = LockFreeStack::with_reclaim_and_backoff(Epoch::new(), backoff);
+            thread::scope(|s| {
+                for i in 0..4 {
+                    let stack = &stack;
+                    s.spawn(move || {
+                        for j in 0..10000 {
+                            stack.push(i * 10000 + j);
+                            stack.pop();
+                        }
+                    });
+                }
+            });
+            assert_eq!(stack.len(), 0);
+        }
+        contend(NoBackoff);
+        contend(ExponentialSpin::new(4));
+        #[cfg(feature = "std")]
+        contend(crate::backoff::SpinThenYield::default());
+        contend(Jitter::new(8));
+    }
+
+    #[test]
+    fn test_pop_epoch() {
+        let mut stack = LockFreeStack::with_reclaim(Epoch::new());
+        for i in 0..100000 {
+            stack.push(i);
+        }
+        thread::scope(|s| {
+            for _ in 0..10 {
+                s.spawn(|| {
+                    for _ in 0..100000 {
+                        let _ = stack.pop();
+                    }
+                });
+            }
+        });
+        assert_eq!(stack.len(), 0)
+    }
+
+    #[test]
+    fn test_drop_frees_remaining_nodes() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let stack = LockFreeStack::new();
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..1000 {
+                        stack.push(DropCounter(drops.clone()));
+                    }
+                });
+            }
+        });
+        assert_eq!(drops.load(Ordering::SeqCst), 0);
+        drop(stack);
+        assert_eq!(drops.load(Ordering::SeqCst), 4000);
+    }
+
+    #[test]
+    fn test_drop_epoch() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let stack = LockFreeStack::with_reclaim(Epoch::new());
+        for _ in 0..1000 {
+            stack.push(DropCounter(drops.clone()));
+        }
+        drop(stack);
+        assert_eq!(drops.load(Ordering::SeqCst), 1000);
+    }
+
+    #[test]
+    fn test_pop_does_not_drop_shared_data() {
+        let value = Arc::new(5);
+        let stack = LockFreeStack::new();
+        for _ in 0..100 {
+            stack.push(value.clone());
+        }
+        assert_eq!(Arc::strong_count(&value), 101);
+
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..25 {
+                        let popped = stack.pop().unwrap();
+                        assert_eq!(*popped, 5);
+                    }
+                });
+            }
+        });
+        assert!(stack.pop().is_none());
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn test_len_does_not_drop_shared_data() {
+        let value = Arc::new(5);
+        let mut stack = LockFreeStack::new();
+        for _ in 0..100 {
+            stack.push(value.clone());
+        }
+        assert_eq!(stack.len(), 100);
+        assert_eq!(Arc::strong_count(&value), 101);
+
+        drop(stack);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn test_history_is_linearizable() {
+        let stack = LockFreeStack::new();
+        let recorder = Recorder::new();
+        let history: Vec<_> = thread::scope(|s| {
+            let handles: Vec<_> = (0..4)
+                .map(|i| {
+                    let stack = &stack;
+                    let recorder = &recorder;
+                    s.spawn(move || {
+                        let mut log = recorder.log();
+                        for j in 0..50 {
+                            if j % 3 == 2 {
+                                log.record(StackOp::Pop, |_| stack.pop());
+                            } else {
+                                let value = i * 50 + j;
+                                log.record(StackOp::Push(value), |_| {
+                                    stack.push(value);
+                                    None
+                                });
+                            }
+                        }
+                        log.into_entries()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|h| h.join().unwrap())
+                .collect()
+        });
+        assert!(linearize(SequentialStack::default(), &history).is_some());
+    }
+
+    #[test]
+    fn test_pop_drops_each_value_once() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let stack = LockFreeStack::new();
+        for _ in 0..1000 {
+            stack.push(DropCounter(drops.clone()));
+        }
+        for _ in 0..500 {
+            drop(stack.pop());
+        }
+        assert_eq!(drops.load(Ordering::SeqCst), 500);
+        drop(stack);
+        assert_eq!(drops.load(Ordering::SeqCst), 1000);
+    }
+
+    #[test]
+    fn test_drop_empty() {
+        let stack: LockFreeStack<DropCounter> = LockFreeStack::new();
+        drop(stack);
+    }
+
+    #[test]
+    fn test_push_many_keeps_iteration_order() {
+        let stack = LockFreeStack::new();
+        stack.push(0);
+        stack.push_many(1..5);
+        stack.push_many([]);
+        assert!(stack.take_all_fifo().eq(0..5));
+    }
+
+    #[test]
+    fn test_push_many_lands_in_one_piece() {
+        let stack = LockFreeStack::new();
+        thread::scope(|s| {
+            for i in 0..4 {
+                let stack = &stack;
+                s.spawn(move || {
+                    for j in 0..1000 {
+                        stack.push_many((0..10).map(|k| (i, j * 10 + k)));
+                    }
+                });
+            }
+        });
+        // every batch is contiguous, so each thread's values show up in sequence
+        let values: Vec<_> = stack.take_all_fifo().collect();
+        assert_eq!(values.len(), 40000);
+        for batch in values.chunks(10) {
+            assert!(batch
+                .windows(2)
+                .all(|pair| pair[0].0 == pair[1].0 && pair[0].1 + 1 == pair[1].1));
+        }
+    }
+
+    #[test]
+    fn test_default_and_from_iterator() {
+        let stack: LockFreeStack<i32, Epoch, Jitter> = LockFreeStack::default();
+        assert!(stack.is_empty());
+        let stack: LockFreeStack<_, Epoch, Jitter> = (0..5).collect();
+        assert!(stack.into_iter().eq((0..5).rev()));
+    }
+
+    #[test]
+    fn test_extend_through_shared_reference() {
+        let mut stack = LockFreeStack::new();
+        stack.extend([0, 1]);
+        thread::scope(|s| {
+            for i in 1..5 {
+                let mut stack = &stack;
+                s.spawn(move || stack.extend([i * 2, i * 2 + 1]));
+            }
+        });
+        let mut values: Vec<_> = stack.into_iter().collect();
+        values.sort();
+        assert_eq!(values, (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_into_iter_drops_remaining_values() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let stack = LockFreeStack::with_reclaim(Epoch::new());
+        for _ in 0..10 {
+            stack.push(DropCounter(drops.clone()));
+        }
+        for _ in 0..3 {
+            drop(stack.pop());
+        }
+        let mut values = stack.into_iter();
+        drop(values.next());
+        assert_eq!(drops.load(Ordering::SeqCst), 4);
+        drop(values);
+        assert_eq!(drops.load(Ordering::SeqCst), 10);
+    }
+
+    #[test]
+    fn test_iterators_move_to_other_threads() {
+        let stack: LockFreeStack<String> = ["a", "b"].map(String::from).into_iter().collect();
+        let consumed = thread::spawn(move || stack.into_iter().collect::<Vec<_>>());
+        assert_eq!(consumed.join().unwrap(), ["b", "a"]);
+
+        let stack = LockFreeStack::with_reclaim(Epoch::new());
+        stack.push(String::from("c"));
+        let taken = stack.take_all();
+        thread::scope(|s| {
+            assert_eq!(
+                s.spawn(move || taken.collect::<Vec<_>>()).join().unwrap(),
+                ["c"]
+            );
+        });
+    }
+
+    #[test]
+    fn test_debug_and_iter_keep_elements() {
+        let mut stack: LockFreeStack<_> = (1..4).collect();
+        assert_eq!(format!("{stack:?}"), "LockFreeStack { approx_len: 3, .. }");
+        assert!(stack.iter().eq(&[3, 2, 1]));
+        assert!(stack.take_all().eq([3, 2, 1]));
+        assert_eq!(stack.iter().next(), None);
+    }
+
+    #[test]
+    fn test_approx_len_tracks_every_operation() {
+        let mut stack = LockFreeStack::new();
+        stack.push(0);
+        stack.push_many(1..5);
+        assert_eq!(stack.approx_len(), 5);
+        stack.pop();
+        assert_eq!(stack.approx_len(), 4);
+        drop(stack.take_all());
+        assert_eq!(stack.approx_len(), 0);
+        assert!(stack.pop().is_none());
+        assert_eq!(stack.approx_len(), 0);
+
+        thread::scope(|s| {
+            for i in 0..4 {
+                let stack = &stack;
+                s.spawn(move || {
+                    for j in 0..10000 {
+                        stack.push(i * 10000 + j);
+                        // a pop only lowers the counter after the push that raised
+                        // it, so it can never wrap around below zero
+                        assert!(stack.approx_len() < 10000 * 4);
+                        stack.pop();
+                    }
+                });
+            }
+        });
+        assert_eq!(stack.approx_len(), stack.len());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_pop_blocking_waits_for_push() {
+        let stack = LockFreeStack::new();
+        thread::scope(|s| {
+            let consumer = s.spawn(|| stack.pop_blocking());
+            thread::sleep(Duration::from_millis(20));
+            stack.push(1);
+            assert_eq!(consumer.join().unwrap(), 1);
+        });
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_pop_timeout() {
+        let stack = LockFreeStack::new();
+        let start = Instant::now();
+        assert_eq!(stack.pop_timeout(Duration::from_millis(20)), None);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        stack.push(1);
+        assert_eq!(stack.pop_timeout(Duration::from_millis(20)), Some(1));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_blocked_consumers_get_every_value() {
+        let stack = LockFreeStack::with_reclaim(Epoch::new());
+        let sum: usize = thread::scope(|s| {
+            let consumers: Vec<_> = (0..4)
+                .map(|_| {
+                    let stack = &stack;
+                    s.spawn(move || (0..2500).map(|_| stack.pop_blocking()).sum::<usize>())
+                })
+                .collect();
+            for i in 0..2 {
+                let stack = &stack;
+                s.spawn(move || {
+                    for j in 0..5000 {
+                        if j % 10 == 0 {
+                            stack.push_many([i * 5000 + j]);
+                        } else {
+                            stack.push(i * 5000 + j);
+                        }
+                    }
+                });
+            }
+            consumers.into_iter().map(|h| h.join().unwrap()).sum()
+        });
+        assert_eq!(sum, (0..10000).sum());
+    }
+
+    #[test]
+    fn test_pop_async() {
+        let stack = LockFreeStack::new();
+        stack.push(1);
+        assert_eq!(block_on(stack.pop_async()), 1);
+        thread::scope(|s| {
+            let consumer = s.spawn(|| block_on(stack.pop_async()));
+            thread::sleep(Duration::from_millis(20));
+            stack.push(2);
+            assert_eq!(consumer.join().unwrap(), 2);
+        });
+    }
+
+    #[test]
+    fn test_pop_async_is_send() {
+        fn assert_send<F: Send>(_: &F) {}
+        let stack = LockFreeStack::<Box<u8>>::new();
+        assert_send(&stack.pop_async());
+    }
+
+    #[test]
+    fn test_dropped_pop_async_passes_its_wakeup_on() {
+        let stack = LockFreeStack::new();
+        let wakers = [
+            Arc::new(CountingWaker::default()),
+            Arc::new(CountingWaker::default()),
+        ];
+        let mut futures = vec![Box::pin(stack.pop_async()), Box::pin(stack.pop_async())];
+        for (future, waker) in futures.iter_mut().zip(&wakers) {
+            let waker = Waker::from(waker.clone());
+            assert!(future
+                .as_mut()
+                .poll(&mut Context::from_waker(&waker))
+                .is_pending());
+        }
+
+        stack.push(1);
+        let woken = wakers
+            .iter()
+            .position(|w| w.0.load(Ordering::SeqCst) == 1)
+            .unwrap();
+        let other = 1 - woken;
+        assert_eq!(wakers[other].0.load(Ordering::SeqCst), 0);
+        // the woken future is cancelled, the other one has to take the element
+        drop(futures.remove(woken));
+        assert_eq!(wakers[other].0.load(Ordering::SeqCst), 1);
+        let waker = Waker::from(wakers[other].clone());
+        assert_eq!(
+            futures[0].as_mut().poll(&mut Context::from_waker(&waker)),
+            Poll::Ready(1)
+        );
+    }
+
+    #[test]
+    fn test_async_consumers_get_every_value() {
+        let stack = LockFreeStack::with_reclaim(Epoch::new());
+        let sum: usize = thread::scope(|s| {
+            let consumers: Vec<_> = (0..4)
+                .map(|_| {
+                    let stack = &stack;
+                    s.spawn(move || {
+                        (0..2500)
+                            .map(|_| block_on(stack.pop_async()))
+                            .sum::<usize>()
+                    })
+                })
+                .collect();
+            for i in 0..2 {
+                let stack = &stack;
+                s.spawn(move || {
+                    for j in 0..5000 {
+                        stack.push(i * 5000 + j);
+                    }
+                });
+            }
+            consumers.into_iter().map(|h| h.join().unwrap()).sum()
+        });
+        assert_eq!(sum, (0..10000).sum());
+    }
+
+    #[test]
+    fn test_peek() {
+        let stack = LockFreeStack::new();
+        assert!(stack.peek().is_none());
+        stack.push(1);
+        stack.push(2);
+        let top = stack.peek().unwrap();
+        assert_eq!(stack.pop(), Some(2));
+        // the value was popped, but the guard keeps the node around
+        assert_eq!(*top, 2);
+        drop(top);
+        assert_eq!(stack.peek().as_deref(), Some(&1));
+    }
+
+    #[test]
+    fn test_peek_during_pops() {
+        let stack = LockFreeStack::with_reclaim(Epoch::new());
+        thread::scope(|s| {
+            for i in 0..2 {
+                let stack = &stack;
+                s.spawn(move || {
+                    for j in 0..10000 {
+                        stack.push([i, j, i + j]);
+                        stack.pop();
+                    }
+                });
+            }
+            s.spawn(|| {
+                for _ in 0..10000 {
+                    if let Some(top) = stack.peek() {
+                        let [i, j, sum] = *top;
+                        assert_eq!(i + j, sum);
+                    }
+                }
+            });
+        });
+    }
+
+    #[test]
+    fn test_take_all_orders() {
+        let stack = LockFreeStack::new();
+        for i in 0..5 {
+            stack.push(i);
+        }
+        assert!(stack.take_all().eq([4, 3, 2, 1, 0]));
+        assert!(stack.is_empty());
+        for i in 0..5 {
+            stack.push(i);
+        }
+        assert!(stack.take_all_fifo().eq(0..5));
+        assert!(stack.take_all().next().is_none());
+    }
+
+    #[test]
+    fn test_take_all_drops_unconsumed_values() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let stack = LockFreeStack::new();
+        for _ in 0..10 {
+            stack.push(DropCounter(drops.clone()));
+        }
+        let mut taken = stack.take_all_fifo();
+        drop(taken.next());
+        assert_eq!(drops.load(Ordering::SeqCst), 1);
+        drop(taken);
+        assert_eq!(drops.load(Ordering::SeqCst), 10);
+        drop(stack);
+        assert_eq!(drops.load(Ordering::SeqCst), 10);
+    }
+
+    #[test]
+    fn test_take_all_during_pops() {
+        let stack = LockFreeStack::with_reclaim(Epoch::new());
+        let taken = AtomicUsize::new(0);
+        let popped = AtomicUsize::new(0);
+        thread::scope(|s| {
+            for i in 0..4 {
+                let (stack, popped) = (&stack, &popped);
+                s.spawn(move || {
+                    for j in 0..10000 {
+                        stack.push(i * 10000 + j);
+                        if stack.pop().is_some() {
+                            popped.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                });
+            }
+            s.spawn(|| {
+                for _ in 0..1000 {
+                    taken.fetch_add(stack.take_all().count(), Ordering::Relaxed);
+                }
+            });
+        });
+        let remaining = stack.take_all().count();
+        assert_eq!(taken.into_inner() + popped.into_inner() + remaining, 40000);
+    }
+
+    #[test]
+    fn test_pool_reuses_popped_nodes() {
+        let mut stack = LockFreeStack::new();
+        stack.set_pool_capacity(16);
+        // hazard pointers only free retired nodes once enough have piled up
+        for i in 0..200 {
+            stack.push(i);
+            stack.pop();
+        }
+        assert_eq!(stack.pool.owned.load(Ordering::Relaxed), 16);
+        let reused = stack.pool.free.load(Ordering::Relaxed).ptr();
+        stack.push(7);
+        assert_eq!(stack.head.load(Ordering::Relaxed).ptr(), reused);
+
+        // the node in use leaves the pool, the free ones shrink down to the rest
+        stack.set_pool_capacity(2);
+        assert_eq!(stack.pool.owned.load(Ordering::Relaxed), 2);
+        assert!(!unsafe { (*reused).pooled });
+        assert_eq!(stack.pop(), Some(7));
+        stack.set_pool_capacity(0);
+        assert_eq!(stack.pool.owned.load(Ordering::Relaxed), 0);
+        assert!(stack.pool.free.load(Ordering::Relaxed).is_null());
+    }
+
+    #[test]
+    fn test_pool_drops_each_value_once() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let mut stack = LockFreeStack::with_reclaim(Epoch::new());
+        stack.set_pool_capacity(64);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                let (stack, drops) = (&stack, &drops);
+                s.spawn(move || {
+                    for _ in 0..10000 {
+                        stack.push(DropCounter(drops.clone()));
+                        stack.push_many([DropCounter(drops.clone())]);
+                        drop(stack.pop());
+                    }
+                });
+            }
+        });
+        assert_eq!(drops.load(Ordering::SeqCst), 40000);
+        assert_eq!(stack.len(), 40000);
+        drop(stack);
+        assert_eq!(drops.load(Ordering::SeqCst), 80000);
+    }
+
+    #[test]
+    fn test_allocator_gets_every_node_back() {
+        let allocator = CountingAllocator::default();
+        let live = || allocator.0.load(Ordering::SeqCst);
+
+        let stack =
+            LockFreeStack::with_reclaim_backoff_and_allocator(Epoch::new(), NoBackoff, &allocator);
+        stack.push_many(0..10);
+        assert_eq!(live(), 10);
+        assert!(ptr::eq(*stack.allocator(), &allocator));
+        stack.pop();
+        assert_eq!(stack.take_all().take(3).count(), 3);
+        stack.push(0);
+        drop(stack);
+        assert_eq!(live(), 0);
+
+        let mut stack = LockFreeStack::with_reclaim_backoff_and_allocator(
+            HazardPointers::new(),
+            NoBackoff,
+            &allocator,
+        );
+        stack.set_pool_capacity(8);
+        for i in 0..200 {
+            stack.push(i);
+            stack.pop();
+        }
+        // the pool keeps some nodes, the rest went back
+        assert!(live() <= 8 + 64);
+        stack.push_many(0..10);
+        assert_eq!(stack.into_iter().take(5).count(), 5);
+        assert_eq!(live(), 0);
+    }
+
+    /// Hands out a fixed number of blocks in total.
+    struct LimitedAllocator(AtomicUsize);
+
+    unsafe impl NodeAllocator for LimitedAllocator {
+        fn allocate(&self, layout: Layout) -> Option<NonNull<u8>> {
+            self.0
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| {
+                    left.checked_sub(1)
+                })
+                .ok()?;
+            Global.allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            unsafe { Global.deallocate(ptr, layout) }
+        }
+    }
+
+    #[test]
+    fn test_try_push_hands_values_back_when_out_of_memory() {
+        let stack = LockFreeStack::with_allocator(LimitedAllocator(AtomicUsize::new(4)));
+        assert_eq!(stack.try_push(1), Ok(()));
+        let mut items = 2..10;
+        assert_eq!(stack.try_push_many(items.by_ref()), Err(5));
+        assert_eq!(items, 6..10);
+        assert_eq!(stack.try_push(6), Err(6));
+        assert_eq!(stack.approx_len(), 4);
+        assert!(stack.take_all().eq([4, 3, 2, 1]));
+    }
+}
@@ -0,0 +1,43 @@
+//! The atomics the node-based structures are built on.
+//!
+//! Under the `loom` feature they are loom's, so the model checker can explore
+//! every interleaving of them. Those only work inside `loom::model`, which is why
+//! the unit tests are compiled out with the feature.
+
+pub(crate) use core::sync::atomic::Ordering;
+#[cfg(not(feature = "loom"))]
+pub(crate) use core::sync::atomic::{fence, AtomicBool, AtomicPtr, AtomicUsize};
+#[cfg(feature = "loom")]
+pub(crate) use loom::sync::atomic::{fence, AtomicBool, AtomicPtr, AtomicUsize};
+
+// tagged pointers pack into one of these on 32-bit targets
+#[cfg(all(
+    target_pointer_width = "32",
+    target_has_atomic = "64",
+    not(feature = "loom")
+))]
+pub(crate) use core::sync::atomic::AtomicU64;
+#[cfg(all(
+    target_pointer_width = "32",
+    target_has_atomic = "64",
+    feature = "loom"
+))]
+pub(crate) use loom::sync::atomic::AtomicU64;
+
+/// The weakest orderings the stack is correct with, all replaced by `SeqCst` under
+/// the `seqcst` feature.
+pub(crate) mod order {
+    use super::Ordering;
+
+    const fn or_seqcst(order: Ordering) -> Ordering {
+        if cfg!(feature = "seqcst") {
+            Ordering::SeqCst
+        } else {
+            order
+        }
+    }
+
+    pub(crate) const RELAXED: Ordering = or_seqcst(Ordering::Relaxed);
+    pub(crate) const ACQUIRE: Ordering = or_seqcst(Ordering::Acquire);
+    pub(crate) const RELEASE: Ordering = or_seqcst(Ordering::Release);
+}
@@ -0,0 +1,304 @@
+//! Atomic pointers carrying a version tag.
+//!
+//! Every successful compare-exchange on an [`AtomicTaggedPtr`] bumps the tag, so a
+//! CAS whose expected value was loaded before the pointer was swapped out and back
+//! in again fails even if the address is the same (the ABA problem).
+//!
+//! Where the tag lives depends on the target:
+//! - On x86_64 and aarch64 it takes bits 48 to 55, which user-space addresses
+//!   leave unused. The top byte stays with the pointer, heap tagging (MTE, HWASan,
+//!   Intel LAM) may be turned on at runtime and keep tags of its own there.
+//! - 32-bit targets with 64-bit atomics pack the pointer and a 32-bit tag into one
+//!   `u64`.
+//! - Everywhere else it only gets the low bits that are always zero because of the
+//!   pointee's alignment, so it wraps around after a handful of updates.
+//!
+//! [`TaggedPtr::TAG_BITS`] says how wide the tag is.
+
+use crate::{reclaim::Link, sync::Ordering};
+use core::{fmt, marker::PhantomData};
+
+#[cfg(all(
+    target_pointer_width = "64",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+// every scheme takes the pointee type, only the alignment one needs it
+#[allow(clippy::extra_unused_type_parameters)]
+mod repr {
+    pub(super) type Raw = *mut u8;
+    pub(super) type Atomic = crate::sync::AtomicPtr<u8>;
+
+    // the top byte is left to hardware tags, which pass through untouched
+    const TAG_MASK: usize = 0xff << 48;
+
+    pub(super) const fn tag_bits<T>() -> u32 {
+        8
+    }
+
+    pub(super) fn pack<T>(ptr: *mut T, tag: usize) -> *mut u8 {
+        assert_eq!(ptr.addr() & TAG_MASK, 0, "pointer overlaps the tag bits");
+        ptr.cast::<u8>()
+            .map_addr(|addr| addr | (tag << 48 & TAG_MASK))
+    }
+
+    pub(super) fn ptr<T>(raw: *mut u8) -> *mut T {
+        raw.map_addr(|addr| addr & !TAG_MASK).cast()
+    }
+
+    pub(super) fn tag<T>(raw: *mut u8) -> usize {
+        (raw.addr() & TAG_MASK) >> 48
+    }
+}
+
+#[cfg(all(target_pointer_width = "32", target_has_atomic = "64"))]
+#[allow(clippy::extra_unused_type_parameters)]
+mod repr {
+    pub(super) type Raw = u64;
+    pub(super) type Atomic = crate::sync::AtomicU64;
+
+    pub(super) const fn tag_bits<T>() -> u32 {
+        32
+    }
+
+    // the pointer goes through an integer, so its provenance has to be exposed
+    pub(super) fn pack<T>(ptr: *mut T, tag: usize) -> u64 {
+        ptr.expose_provenance() as u64 | (tag as u64) << 32
+    }
+
+    pub(super) fn ptr<T>(raw: u64) -> *mut T {
+        core::ptr::with_exposed_provenance_mut(raw as u32 as usize)
+    }
+
+    pub(super) fn tag<T>(raw: u64) -> usize {
+        (raw >> 32) as usize
+    }
+}
+
+#[cfg(not(any(
+    all(
+        target_pointer_width = "64",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ),
+    all(target_pointer_width = "32", target_has_atomic = "64")
+)))]
+mod repr {
+    pub(super) type Raw = *mut u8;
+    pub(super) type Atomic = crate::sync::AtomicPtr<u8>;
+
+    pub(super) const fn tag_bits<T>() -> u32 {
+        core::mem::align_of::<T>().trailing_zeros()
+    }
+
+    const fn tag_mask<T>() -> usize {
+        core::mem::align_of::<T>() - 1
+    }
+
+    pub(super) fn pack<T>(ptr: *mut T, tag: usize) -> *mut u8 {
+        assert_eq!(
+            ptr.addr() & tag_mask::<T>(),
+            0,
+            "pointer overlaps the tag bits"
+        );
+        ptr.cast::<u8>()
+            .map_addr(|addr| addr | (tag & tag_mask::<T>()))
+    }
+
+    pub(super) fn ptr<T>(raw: *mut u8) -> *mut T {
+        raw.map_addr(|addr| addr & !tag_mask::<T>()).cast()
+    }
+
+    pub(super) fn tag<T>(raw: *mut u8) -> usize {
+        raw.addr() & tag_mask::<T>()
+    }
+}
+
+/// A pointer together with its version tag.
+pub struct TaggedPtr<T> {
+    raw: repr::Raw,
+    _marker: PhantomData<*mut T>,
+}
+
+impl<T> TaggedPtr<T> {
+    /// How many bits the tag has. It wraps around after `2^TAG_BITS` updates.
+    pub const TAG_BITS: u32 = repr::tag_bits::<T>();
+
+    /// Panics if the address of `ptr` overlaps the bits the tag lives in.
+    pub fn new(ptr: *mut T, tag: usize) -> Self {
+        Self::from_raw(repr::pack(ptr, tag))
+    }
+
+    fn from_raw(raw: repr::Raw) -> Self {
+        Self {
+            raw,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn ptr(self) -> *mut T {
+        repr::ptr::<T>(self.raw)
+    }
+
+    pub fn tag(self) -> usize {
+        repr::tag::<T>(self.raw)
+    }
+
+    pub fn is_null(self) -> bool {
+        self.ptr().is_null()
+    }
+}
+
+impl<T> Clone for TaggedPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for TaggedPtr<T> {}
+
+impl<T> PartialEq for TaggedPtr<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.raw == other.raw
+    }
+}
+
+impl<T> Eq for TaggedPtr<T> {}
+
+impl<T> fmt::Debug for TaggedPtr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TaggedPtr")
+            .field("ptr", &self.ptr())
+            .field("tag", &self.tag())
+            .finish()
+    }
+}
+
+/// An atomic pointer whose value is versioned on every successful compare-exchange.
+pub struct AtomicTaggedPtr<T> {
+    raw: repr::Atomic,
+    _marker: PhantomData<core::sync::atomic::AtomicPtr<T>>,
+}
+
+impl<T> AtomicTaggedPtr<T> {
+    pub fn new(ptr: *mut T) -> Self {
+        Self {
+            raw: repr::Atomic::new(TaggedPtr::new(ptr, 0).raw),
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn load(&self, order: Ordering) -> TaggedPtr<T> {
+        TaggedPtr::from_raw(self.raw.load(order))
+    }
+
+    /// Replaces `current` with `new` and the next tag if the value has not changed
+    /// since `current` was loaded, returning the actual value otherwise.
+    pub fn compare_exchange_weak(
+        &self,
+        current: TaggedPtr<T>,
+        new: *mut T,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<TaggedPtr<T>, TaggedPtr<T>> {
+        let new = TaggedPtr::new(new, current.tag().wrapping_add(1));
+        self.raw
+            .compare_exchange_weak(current.raw, new.raw, success, failure)
+            .map(TaggedPtr::from_raw)
+            .map_err(TaggedPtr::from_raw)
+    }
+
+    /// Like [`compare_exchange_weak`](Self::compare_exchange_weak), but never fails
+    /// spuriously.
+    pub fn compare_exchange(
+        &self,
+        current: TaggedPtr<T>,
+        new: *mut T,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<TaggedPtr<T>, TaggedPtr<T>> {
+        let new = TaggedPtr::new(new, current.tag().wrapping_add(1));
+        self.raw
+            .compare_exchange(current.raw, new.raw, success, failure)
+            .map(TaggedPtr::from_raw)
+            .map_err(TaggedPtr::from_raw)
+    }
+
+    /// Stores `new` with the next tag and returns the previous value.
+    pub fn swap(&self, new: *mut T, order: Ordering) -> TaggedPtr<T> {
+        let (Ok(raw) | Err(raw)) = self.raw.fetch_update(order, Ordering::Relaxed, |raw| {
+            Some(TaggedPtr::new(new, TaggedPtr::<T>::from_raw(raw).tag().wrapping_add(1)).raw)
+        });
+        TaggedPtr::from_raw(raw)
+    }
+}
+
+impl<T> Link for AtomicTaggedPtr<T> {
+    type Ptr = TaggedPtr<T>;
+
+    fn load(&self, order: Ordering) -> TaggedPtr<T> {
+        self.load(order)
+    }
+
+    fn address(ptr: TaggedPtr<T>) -> *mut u8 {
+        ptr.ptr().cast()
+    }
+}
+
+#[cfg(all(test, not(feature = "loom")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tag_roundtrip() {
+        let mut value = 5_u64;
+        let tagged = TaggedPtr::new(&mut value as *mut u64, 3);
+        assert_eq!(tagged.ptr(), &mut value as *mut u64);
+        assert_eq!(tagged.tag(), 3);
+    }
+
+    fn swap_in(atomic: &AtomicTaggedPtr<u64>, new: *mut u64) {
+        let mut current = atomic.load(Ordering::SeqCst);
+        while let Err(actual) =
+            atomic.compare_exchange_weak(current, new, Ordering::SeqCst, Ordering::SeqCst)
+        {
+            current = actual;
+        }
+    }
+
+    #[test]
+    fn test_stale_compare_exchange_fails() {
+        let mut a = 1_u64;
+        let mut b = 2_u64;
+        let atomic = AtomicTaggedPtr::new(&mut a as *mut u64);
+        let stale = atomic.load(Ordering::SeqCst);
+
+        // A -> B -> A, the address is the same again but the version is not
+        swap_in(&atomic, &mut b);
+        swap_in(&atomic, &mut a);
+
+        let current = atomic.load(Ordering::SeqCst);
+        assert_eq!(current.ptr(), stale.ptr());
+        assert_eq!(current.tag(), stale.tag() + 2);
+        assert!(atomic
+            .compare_exchange_weak(stale, &mut b, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err());
+    }
+
+    #[test]
+    #[cfg(all(
+        target_pointer_width = "64",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))]
+    fn test_top_byte_stays_with_the_pointer() {
+        let hardware_tagged = core::ptr::without_provenance_mut::<u64>(0x5a << 56 | 0x1000);
+        let tagged = TaggedPtr::new(hardware_tagged, 0x1ff);
+        assert_eq!(tagged.ptr(), hardware_tagged);
+        assert_eq!(tagged.tag(), 0xff);
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    #[should_panic(expected = "pointer overlaps the tag bits")]
+    fn test_overlapping_pointer_is_rejected() {
+        TaggedPtr::new(core::ptr::without_provenance_mut::<u64>(usize::MAX), 0);
+    }
+}
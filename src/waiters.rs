@@ -0,0 +1,209 @@
+//! A lock-free list of threads and tasks waiting for a structure to change.
+//!
+//! A waiter claims a record, stores how to wake it and marks the record as waiting.
+//! Whoever changes the structure then notifies waiting records, and the waiter
+//! checks the structure again once it is woken.
+
+use crate::{
+    records::RecordList,
+    sync::{AtomicUsize, Ordering},
+};
+use core::{cell::UnsafeCell, hint, mem, task::Waker};
+#[cfg(feature = "std")]
+use std::{
+    thread::{self, Thread},
+    time::Instant,
+};
+
+// The states of a record. Only the thread that moves a record out of `FREE` or
+// into `NOTIFYING` may touch its `unparker` until it moves it on again.
+const FREE: usize = 0;
+const CLAIMED: usize = 1;
+const WAITING: usize = 2;
+const NOTIFYING: usize = 3;
+const NOTIFIED: usize = 4;
+
+/// How to wake a waiter.
+pub(crate) enum Unparker {
+    #[cfg(feature = "std")]
+    Thread(Thread),
+    Waker(Waker),
+}
+
+impl Unparker {
+    fn unpark(self) {
+        match self {
+            #[cfg(feature = "std")]
+            Unparker::Thread(thread) => thread.unpark(),
+            Unparker::Waker(waker) => waker.wake(),
+        }
+    }
+}
+
+struct Record {
+    state: AtomicUsize,
+    unparker: UnsafeCell<Option<Unparker>>,
+}
+
+// The state machine hands `unparker` from one thread to the next, so records can
+// be shared, and a `Registration` can move to another thread with its future.
+unsafe impl Sync for Record {}
+
+pub(crate) struct Waiters {
+    records: RecordList<Record>,
+    // lets `notify` skip the walk when nobody waits
+    waiting: AtomicUsize,
+}
+
+impl Waiters {
+    pub(crate) fn new() -> Self {
+        Self {
+            records: RecordList::new(),
+            waiting: AtomicUsize::new(0),
+        }
+    }
+
+    /// Registers a waiter that `unparker` wakes. The caller must check the
+    /// structure once more afterwards with a `SeqCst` load, a change made before
+    /// the registration will not notify it.
+    pub(crate) fn register(&self, unparker: Unparker) -> Registration<'_> {
+        let record = self.records.claim(
+            |record| {
+                record.state.load(Ordering::Relaxed) == FREE
+                    && record
+                        .state
+                        .compare_exchange(FREE, CLAIMED, Ordering::Acquire, Ordering::Relaxed)
+                        .is_ok()
+            },
+            || Record {
+                state: AtomicUsize::new(CLAIMED),
+                unparker: UnsafeCell::new(None),
+            },
+        );
+        unsafe { *record.unparker.get() = Some(unparker) };
+        record.state.store(WAITING, Ordering::Release);
+        // Counted only once it is waiting, so a notifier that sees the count sees the
+        // record too. A notifier may take the record before it is counted, the count
+        // then wraps around for a moment. Pairs with the load in `notify`: either
+        // the caller's next check sees the change, or the notifier sees this count.
+        self.waiting.fetch_add(1, Ordering::SeqCst);
+        Registration {
+            waiters: self,
+            record,
+        }
+    }
+
+    /// Parks the calling thread until `check` returns something, checking again
+    /// every time it is notified. Once `deadline` has passed, what one last check
+    /// returns is the result.
+    #[cfg(feature = "std")]
+    pub(crate) fn wait_until<R>(
+        &self,
+        deadline: Option<Instant>,
+        mut check: impl FnMut() -> Option<R>,
+    ) -> Option<R> {
+        loop {
+            if let Some(result) = check() {
+                return Some(result);
+            }
+            let waiter = self.register(Unparker::Thread(thread::current()));
+            // a change between the check above and the registration did not see us
+            if let Some(result) = check() {
+                waiter.cancel();
+                return Some(result);
+            }
+            while !waiter.is_notified() {
+                match deadline {
+                    None => thread::park(),
+                    Some(deadline) => {
+                        let now = Instant::now();
+                        if now >= deadline {
+                            waiter.cancel();
+                            return check();
+                        }
+                        thread::park_timeout(deadline - now);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Wakes up to `count` waiters. Must be called after the change they wait for,
+    /// which has to be a `SeqCst` read-modify-write so it cannot be reordered with
+    /// the load of the waiter count.
+    pub(crate) fn notify(&self, mut count: usize) {
+        if self.waiting.load(Ordering::SeqCst) == 0 {
+            return;
+        }
+        for record in self.records.iter() {
+            if count == 0 {
+                break;
+            }
+            if record
+                .state
+                .compare_exchange(WAITING, NOTIFYING, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                self.waiting.fetch_sub(1, Ordering::Relaxed);
+                let unparker = unsafe { (*record.unparker.get()).take() };
+                record.state.store(NOTIFIED, Ordering::Release);
+                if let Some(unparker) = unparker {
+                    unparker.unpark();
+                }
+                count -= 1;
+            }
+        }
+    }
+}
+
+/// A registered waiter. Dropping it releases its record.
+pub(crate) struct Registration<'a> {
+    waiters: &'a Waiters,
+    record: &'a Record,
+}
+
+impl Registration<'_> {
+    #[cfg(feature = "std")]
+    fn is_notified(&self) -> bool {
+        self.record.state.load(Ordering::Acquire) == NOTIFIED
+    }
+
+    /// Withdraws a waiter that no longer needs waking. A notification that got to
+    /// it anyway is passed on, since the caller will not act on it.
+    pub(crate) fn cancel(self) {
+        let notified = self.release();
+        let waiters = self.waiters;
+        mem::forget(self);
+        if notified {
+            waiters.notify(1);
+        }
+    }
+
+    /// Frees the record and returns whether it had been notified.
+    fn release(&self) -> bool {
+        let state = &self.record.state;
+        let notified =
+            match state.compare_exchange(WAITING, CLAIMED, Ordering::Acquire, Ordering::Acquire) {
+                Ok(_) => {
+                    self.waiters.waiting.fetch_sub(1, Ordering::Relaxed);
+                    drop(unsafe { (*self.record.unparker.get()).take() });
+                    false
+                }
+                Err(_) => {
+                    // a notifier is taking the unparker out, it is done in a moment
+                    while state.load(Ordering::Acquire) != NOTIFIED {
+                        hint::spin_loop();
+                    }
+                    true
+                }
+            };
+        state.store(FREE, Ordering::Release);
+        notified
+    }
+}
+
+impl Drop for Registration<'_> {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
@@ -0,0 +1,236 @@
+//! Model-checked tests of the stack.
+//!
+//! Every test explores all interleavings of its threads. Run with
+//! `cargo test --release --features loom --test loom`, and add the `seqcst`
+//! feature to check the `SeqCst` variant.
+
+#![cfg(feature = "loom")]
+
+use lock_free::{backoff::NoBackoff, reclaim::Reclaim, Epoch, HazardPointers, LockFreeStack};
+use loom::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+/// A shared stack that retries right away, backing off would only add steps to
+/// the model.
+fn stack<T, R: Reclaim>(reclaim: R) -> Arc<LockFreeStack<T, R, NoBackoff>> {
+    Arc::new(LockFreeStack::with_reclaim_and_backoff(reclaim, NoBackoff))
+}
+
+fn spawn_push<T: Send + 'static, R: Reclaim + 'static>(
+    stack: &Arc<LockFreeStack<T, R, NoBackoff>>,
+    value: T,
+) -> thread::JoinHandle<()> {
+    let stack = stack.clone();
+    thread::spawn(move || stack.push(value))
+}
+
+fn spawn_pop<T: Send + 'static, R: Reclaim + 'static>(
+    stack: &Arc<LockFreeStack<T, R, NoBackoff>>,
+) -> thread::JoinHandle<Option<T>> {
+    let stack = stack.clone();
+    thread::spawn(move || stack.pop())
+}
+
+#[test]
+fn pop_sees_writes_made_before_push() {
+    loom::model(|| {
+        let stack = stack(HazardPointers::new());
+        let written = Arc::new(AtomicUsize::new(0));
+
+        let pusher = {
+            let stack = stack.clone();
+            let written = written.clone();
+            thread::spawn(move || {
+                written.store(1, Ordering::Relaxed);
+                stack.push(1);
+            })
+        };
+        if stack.pop().is_some() {
+            assert_eq!(written.load(Ordering::Relaxed), 1);
+        }
+        pusher.join().unwrap();
+    });
+}
+
+#[test]
+fn pop_sees_writes_made_before_a_push_below_the_head() {
+    loom::model(|| {
+        let stack = stack(Epoch::new());
+        let written = Arc::new(AtomicUsize::new(0));
+
+        let pusher = {
+            let stack = stack.clone();
+            let written = written.clone();
+            thread::spawn(move || {
+                written.store(1, Ordering::Relaxed);
+                stack.push(1);
+            })
+        };
+        let popper = spawn_pop(&stack);
+        // a pop in between may leave the pushed node as the new head, this pop then
+        // only reads it through the first pop's relaxed CAS
+        if stack.pop() == Some(1) {
+            assert_eq!(written.load(Ordering::Relaxed), 1);
+        }
+        pusher.join().unwrap();
+        popper.join().unwrap();
+    });
+}
+
+#[test]
+fn concurrent_pushes_are_all_popped() {
+    loom::model(|| {
+        let stack = stack(HazardPointers::new());
+        let pushers = [spawn_push(&stack, 0), spawn_push(&stack, 1)];
+        for pusher in pushers {
+            pusher.join().unwrap();
+        }
+        let mut popped = [stack.pop().unwrap(), stack.pop().unwrap()];
+        popped.sort();
+        assert_eq!(popped, [0, 1]);
+        assert_eq!(stack.pop(), None);
+    });
+}
+
+#[test]
+fn concurrent_pops_take_different_values() {
+    fn check<R: Reclaim + 'static>(reclaim: R) {
+        let stack = stack(reclaim);
+        stack.push(0);
+        stack.push(1);
+        let popper = spawn_pop(&stack);
+        let mine = stack.pop().unwrap();
+        let theirs = popper.join().unwrap().unwrap();
+        assert_ne!(mine, theirs);
+        assert_eq!(stack.pop(), None);
+    }
+    loom::model(|| check(HazardPointers::new()));
+    loom::model(|| check(Epoch::new()));
+}
+
+#[test]
+fn concurrent_push_and_pop() {
+    fn check<R: Reclaim + 'static>(reclaim: R) {
+        let stack = stack(reclaim);
+        stack.push(0);
+        let pusher = spawn_push(&stack, 1);
+        let popped = stack.pop().unwrap();
+        pusher.join().unwrap();
+        // the pop linearizes either before or after the push
+        let rest = stack.pop().unwrap();
+        assert!(matches!((popped, rest), (0, 1) | (1, 0)));
+        assert_eq!(stack.pop(), None);
+    }
+    loom::model(|| check(HazardPointers::new()));
+    loom::model(|| check(Epoch::new()));
+}
+
+#[test]
+fn pop_on_empty_stack() {
+    loom::model(|| {
+        let stack = stack::<i32, _>(HazardPointers::new());
+        let popper = spawn_pop(&stack);
+        assert_eq!(stack.pop(), None);
+        assert_eq!(popper.join().unwrap(), None);
+        assert!(stack.is_empty());
+    });
+}
+
+#[test]
+fn pop_racing_the_first_push() {
+    loom::model(|| {
+        let stack = stack(HazardPointers::new());
+        let pusher = spawn_push(&stack, 1);
+        let popped = stack.pop();
+        pusher.join().unwrap();
+        // either the pop saw an empty stack and the value is still there, or it
+        // took the value
+        match popped {
+            Some(value) => assert_eq!(value, 1),
+            None => assert_eq!(stack.pop(), Some(1)),
+        }
+        assert_eq!(stack.pop(), None);
+    });
+}
+
+#[test]
+fn drop_with_remaining_elements() {
+    fn check<R: Reclaim + 'static>(reclaim: R) {
+        let value = Arc::new(());
+        let stack = stack(reclaim);
+        stack.push(value.clone());
+        let pushers = [
+            spawn_push(&stack, value.clone()),
+            spawn_push(&stack, value.clone()),
+        ];
+        drop(stack.pop());
+        for pusher in pushers {
+            pusher.join().unwrap();
+        }
+        assert_eq!(Arc::strong_count(&value), 3);
+        drop(stack);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+    loom::model(|| check(HazardPointers::new()));
+    loom::model(|| check(Epoch::new()));
+}
+
+#[test]
+fn take_all_racing_a_pop() {
+    loom::model(|| {
+        let stack = stack(HazardPointers::new());
+        stack.push(0);
+        stack.push(1);
+        let popper = spawn_pop(&stack);
+        let mut values: Vec<_> = stack.take_all_fifo().collect();
+        values.extend(popper.join().unwrap());
+        values.sort();
+        assert_eq!(values, [0, 1]);
+    });
+}
+
+#[test]
+fn peek_racing_a_pop() {
+    loom::model(|| {
+        let stack = stack(HazardPointers::new());
+        stack.push(1);
+        let popper = spawn_pop(&stack);
+        if let Some(top) = stack.peek() {
+            assert_eq!(*top, 1);
+        }
+        assert_eq!(popper.join().unwrap(), Some(1));
+    });
+}
+
+#[test]
+fn pushes_racing_for_a_pooled_node() {
+    // both pushes protect the pooled node through a guard of their own, which makes
+    // the full search too big; the ABA needs few preemptions anyway
+    let mut builder = loom::model::Builder::new();
+    builder.preemption_bound = Some(3);
+    builder.check(|| {
+        let mut stack = LockFreeStack::with_reclaim_and_backoff(HazardPointers::new(), NoBackoff);
+        stack.set_pool_capacity(1);
+        stack.push(0);
+        stack.pop();
+        // hands the retired node over to the pool
+        stack.set_pool_capacity(1);
+        let stack = Arc::new(stack);
+
+        let pushers = [spawn_push(&stack, 1), spawn_push(&stack, 2)];
+        let popped = stack.pop();
+        for pusher in pushers {
+            pusher.join().unwrap();
+        }
+        let mut values: Vec<_> = popped.into_iter().collect();
+        values.extend(stack.pop());
+        values.extend(stack.pop());
+        values.sort();
+        assert_eq!(values, [1, 2]);
+    });
+}